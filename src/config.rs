@@ -1,11 +1,25 @@
-use serde::Deserialize;
-use std::{io::Result, path::PathBuf};
+use nix::errno::Errno;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::{io::Result, net::IpAddr, path::PathBuf};
+
+use crate::SysNum;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub log: LogConfig,
     pub record: RecordConfig,
     pub redirect: RedirectConfig,
+    #[serde(default, deserialize_with = "deserialize_deny_rules")]
+    pub deny: Vec<DenyRule>,
+    #[serde(default)]
+    pub faults: FaultConfig,
+    // Whether the program may reach other hosts. Blocked attempts are always
+    // recorded, even without `record.network`.
+    #[serde(default)]
+    pub network: NetworkMode,
+    // How time calls are kept from bypassing the tracer through the vDSO.
+    #[serde(default)]
+    pub vdso: VdsoMode,
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,16 +38,193 @@ pub enum LogLevel {
     ERROR,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    #[default]
+    Online,
+    // Connections and datagrams to anything but the local host fail with
+    // ENETUNREACH.
+    Offline,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VdsoMode {
+    // Hide the vDSO from the C library, which falls back to syscalls.
+    #[default]
+    Disable,
+    // Keep the vDSO, but replace its time functions with syscalls. Programs
+    // that require a vDSO keep working, and its other functions stay fast.
+    Patch,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RedirectConfig {
     pub files: Vec<Redirect>,
-    pub random: bool,
-    pub time: Option<u64>,
+    // Outgoing connections, from `host:port`, an address or a CIDR range to
+    // `ip:port` or a unix socket path.
+    #[serde(default)]
+    pub network: Vec<Redirect>,
+    // Resolve symlinks before matching paths against the redirections.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(deserialize_with = "deserialize_random")]
+    pub random: RandomConfig,
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub time: Option<TimeConfig>,
+    #[serde(default)]
+    pub timestamps: Option<TimestampConfig>,
     pub pid: Option<u32>,
+    #[serde(default)]
+    pub identity: Option<IdentityConfig>,
+    #[serde(default)]
+    pub dns: Option<DnsConfig>,
+    #[serde(default)]
+    pub uname: Option<UnameConfig>,
     pub stdout: Option<PathBuf>,
     pub stderr: Option<PathBuf>,
 }
 
+// `redirect.random` is either a boolean, or these settings which enable the
+// redirection unless told otherwise.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RandomConfig {
+    pub enabled: bool,
+    pub seed: u64,
+    pub algorithm: RandomAlgorithm,
+    // Byte used by the constant algorithm.
+    pub fill: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RandomAlgorithm {
+    // rand's StdRng, whose output may change between releases. The default,
+    // so that existing configurations keep their bytes.
+    #[default]
+    Std,
+    // ChaCha20 from a pinned rand_chacha, stable across releases, so that a
+    // seed gives the same bytes after upgrades.
+    ChaCha20,
+    Constant,
+}
+
+// `redirect.time` is either the realtime clock in seconds since the epoch, or
+// these settings.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeConfig {
+    // Virtual realtime clock at start, in seconds since the epoch.
+    pub realtime: u64,
+    // Virtual monotonic and boot time clocks at start, in seconds. They are
+    // left untouched when unset.
+    #[serde(default)]
+    pub monotonic: Option<u64>,
+    // How the virtual realtime and monotonic clocks move from their start.
+    #[serde(default)]
+    pub mode: TimeMode,
+    // Speed of the virtual clocks relative to the host, with the scaled mode.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    // Nanoseconds the virtual clocks advance on each read, with the step mode.
+    #[serde(default = "default_step")]
+    pub step: u64,
+    // Make sleeps and timeouts expire at once, and move the virtual clocks
+    // forward by their duration instead.
+    #[serde(default)]
+    pub sleep: bool,
+    #[serde(default)]
+    pub cpu: CpuTimePolicy,
+    // Nanoseconds a CPU clock advances each time it is read, with the step
+    // policy.
+    #[serde(default = "default_cpu_step")]
+    pub cpu_step: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeMode {
+    // Never moves.
+    #[default]
+    Frozen,
+    // Follows the host clock, shifted to start at the configured time.
+    Offset,
+    // Follows the host clock, multiplied by `scale`.
+    Scaled,
+    // Advances by `step` on each read, whatever the host clock does.
+    Step,
+}
+
+// User and groups the program believes it runs as.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdentityConfig {
+    pub uid: u32,
+    pub gid: u32,
+    // Name given to the user in /etc/passwd.
+    #[serde(default)]
+    pub username: Option<String>,
+    // Supplementary groups, only the primary group when empty.
+    #[serde(default)]
+    pub groups: Vec<u32>,
+}
+
+// System names returned by uname(), and by the matching files of
+// /proc/sys/kernel. Unset fields keep their real value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UnameConfig {
+    pub sysname: Option<String>,
+    pub nodename: Option<String>,
+    pub release: Option<String>,
+    pub version: Option<String>,
+    pub machine: Option<String>,
+    pub domainname: Option<String>,
+}
+
+// Host names the program resolves, added to copies of /etc/hosts and
+// /etc/nsswitch.conf.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DnsConfig {
+    #[serde(default)]
+    pub hosts: Vec<HostEntry>,
+    // Answer DNS queries sent to port 53 from a resolver on loopback, which
+    // only knows the hosts above. Without it, libc lookups never leave the
+    // hosts file.
+    #[serde(default)]
+    pub resolver: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HostEntry {
+    pub name: String,
+    pub address: IpAddr,
+}
+
+// Timestamps reported by the stat family of syscalls.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimestampConfig {
+    // Seconds since the epoch, like SOURCE_DATE_EPOCH. The virtual realtime
+    // clock is used when unset.
+    #[serde(default)]
+    pub epoch: Option<u64>,
+    // Only rewrite the timestamps of these paths, or of all files when empty.
+    #[serde(default)]
+    pub paths: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuTimePolicy {
+    // The CPU time actually used.
+    #[default]
+    Real,
+    // Always zero.
+    Frozen,
+    // Advances by a fixed amount on each read, per thread and per process.
+    Step,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct RecordConfig {
     pub files: bool,
@@ -41,9 +232,71 @@ pub struct RecordConfig {
     pub time: bool,
     pub pid: bool,
     pub path: PathBuf,
+    // Sockets created and the addresses they connect, bind or send to.
+    #[serde(default)]
+    pub network: bool,
+    // Store the bytes returned by getrandom() along with their length.
+    #[serde(default)]
+    pub random_bytes: Option<BytesEncoding>,
+    #[serde(default)]
+    pub denied: bool,
+    #[serde(default)]
+    pub faults: bool,
+    // Real values returned by uname().
+    #[serde(default)]
+    pub uname: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BytesEncoding {
+    Hex,
+    Base64,
+}
+
+// Syscalls to skip, making them fail with the given errno. A rule matches
+// the syscall, the path, or both when both are set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DenyRule {
+    #[serde(default, deserialize_with = "deserialize_syscall")]
+    pub syscall: Option<SysNum>,
+    pub path: Option<Pattern>,
+    #[serde(deserialize_with = "deserialize_errno")]
+    pub errno: Errno,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FaultConfig {
+    // Seed for probabilistic faults.
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default, deserialize_with = "deserialize_fault_rules")]
+    pub rules: Vec<FaultRule>,
+}
+
+// Faults injected in the syscalls matching the syscall and path, like deny
+// rules, where reads and writes match on the file behind their fd. A fault
+// either fails the syscall with `errno`, or limits the count of a read or
+// write to `short` bytes. It is injected on every `every`-th matching call,
+// with the given probability, or always.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaultRule {
+    #[serde(default, deserialize_with = "deserialize_syscall")]
+    pub syscall: Option<SysNum>,
+    pub path: Option<Pattern>,
+    #[serde(default, deserialize_with = "deserialize_optional_errno")]
+    pub errno: Option<Errno>,
+    pub short: Option<usize>,
+    pub every: Option<u64>,
+    pub probability: Option<f64>,
+}
+
+// Path pattern, where `*` matches any sequence of characters.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Pattern(pub String);
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Redirect {
     // pub redirect_type: RedirectType,
     pub from: String,
@@ -59,6 +312,197 @@ impl Config {
     }
 }
 
+impl Default for RandomConfig {
+    fn default() -> Self {
+        RandomConfig {
+            enabled: true,
+            seed: 0xdeadbeef,
+            algorithm: RandomAlgorithm::default(),
+            fill: 0,
+        }
+    }
+}
+
+fn deserialize_random<'de, D>(deserializer: D) -> std::result::Result<RandomConfig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RandomSetting {
+        Enabled(bool),
+        Config(RandomConfig),
+    }
+
+    Ok(match RandomSetting::deserialize(deserializer)? {
+        RandomSetting::Enabled(enabled) => RandomConfig {
+            enabled,
+            ..Default::default()
+        },
+        RandomSetting::Config(config) => config,
+    })
+}
+
+impl TimeConfig {
+    pub fn frozen(realtime: u64) -> Self {
+        TimeConfig {
+            realtime,
+            monotonic: None,
+            mode: TimeMode::default(),
+            scale: default_scale(),
+            step: default_step(),
+            sleep: false,
+            cpu: CpuTimePolicy::default(),
+            cpu_step: default_cpu_step(),
+        }
+    }
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_step() -> u64 {
+    1_000_000_000
+}
+
+fn default_cpu_step() -> u64 {
+    1_000_000
+}
+
+fn deserialize_time<'de, D>(deserializer: D) -> std::result::Result<Option<TimeConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TimeSetting {
+        Realtime(u64),
+        Config(TimeConfig),
+    }
+
+    Ok(match Option::<TimeSetting>::deserialize(deserializer)? {
+        Some(TimeSetting::Realtime(realtime)) => Some(TimeConfig::frozen(realtime)),
+        Some(TimeSetting::Config(config)) if !config.scale.is_finite() || config.scale < 0.0 => {
+            return Err(D::Error::custom(
+                "the time scale must be a finite number, at least 0",
+            ))
+        }
+        Some(TimeSetting::Config(config)) => Some(config),
+        None => None,
+    })
+}
+
+// Rules that would match nothing are rejected.
+fn deserialize_deny_rules<'de, D>(deserializer: D) -> std::result::Result<Vec<DenyRule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = Vec::<DenyRule>::deserialize(deserializer)?;
+    if rules
+        .iter()
+        .any(|rule| rule.syscall.is_none() && rule.path.is_none())
+    {
+        return Err(D::Error::custom("deny rules need a syscall or a path"));
+    }
+    Ok(rules)
+}
+
+// Rules that would inject nothing are rejected.
+fn deserialize_fault_rules<'de, D>(deserializer: D) -> std::result::Result<Vec<FaultRule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = Vec::<FaultRule>::deserialize(deserializer)?;
+    if rules
+        .iter()
+        .any(|rule| rule.errno.is_none() && rule.short.is_none())
+    {
+        return Err(D::Error::custom(
+            "fault rules need an errno or a short count",
+        ));
+    }
+    Ok(rules)
+}
+
+impl Pattern {
+    pub fn matches(&self, path: &str) -> bool {
+        let mut parts = self.0.split('*');
+        // There is always a first part, possibly empty.
+        let first = parts.next().unwrap_or_default();
+        let Some(mut rest) = path.strip_prefix(first) else {
+            return false;
+        };
+        let parts: Vec<&str> = parts.collect();
+        let Some((last, middle)) = parts.split_last() else {
+            // No wildcard.
+            return rest.is_empty();
+        };
+        for part in middle {
+            match rest.find(part) {
+                Some(index) => rest = &rest[index + part.len()..],
+                None => return false,
+            }
+        }
+        rest.ends_with(last)
+    }
+}
+
+fn deserialize_syscall<'de, D>(deserializer: D) -> std::result::Result<Option<SysNum>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(name) => name.parse().map(Some).map_err(D::Error::custom),
+        None => Ok(None),
+    }
+}
+
+// Errors are given by name, e.g. "ENOENT", or by number. Syscalls can only
+// return errnos from 1 to 4095.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrnoSetting {
+    Number(i32),
+    Name(String),
+}
+
+impl TryFrom<ErrnoSetting> for Errno {
+    type Error = String;
+
+    fn try_from(setting: ErrnoSetting) -> std::result::Result<Self, Self::Error> {
+        match setting {
+            ErrnoSetting::Number(num @ 1..=4095) => Ok(Errno::from_raw(num)),
+            ErrnoSetting::Number(num) => Err(format!("invalid errno: {}", num)),
+            ErrnoSetting::Name(name) => (1..256)
+                .map(Errno::from_raw)
+                .find(|errno| format!("{:?}", errno).eq_ignore_ascii_case(&name))
+                .ok_or_else(|| format!("unknown errno: {}", name)),
+        }
+    }
+}
+
+fn deserialize_errno<'de, D>(deserializer: D) -> std::result::Result<Errno, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    ErrnoSetting::deserialize(deserializer)?
+        .try_into()
+        .map_err(D::Error::custom)
+}
+
+fn deserialize_optional_errno<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Errno>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<ErrnoSetting>::deserialize(deserializer)? {
+        Some(setting) => setting.try_into().map(Some).map_err(D::Error::custom),
+        None => Ok(None),
+    }
+}
+
 impl From<&LogLevel> for tracing::Level {
     fn from(value: &LogLevel) -> Self {
         match value {
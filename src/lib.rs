@@ -1,10 +1,12 @@
 pub mod config;
 mod modules;
 mod recorder;
+mod replay;
 mod syscall;
 mod tracer;
 
 pub use config::{Config, SpawnOptions};
 pub use recorder::{Record, Recorder};
+pub use replay::Recording;
 pub use syscall::SysNum;
 pub use tracer::Tracer;
@@ -1,28 +1,46 @@
-use std::process::exit;
+use std::{path::PathBuf, process::exit};
 
-use intercept::{Config, Tracer};
+use intercept::{Config, Recording, Tracer};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use tracing::{debug, error, info, span, Level};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about=None)]
 struct Args {
-    #[arg(short('f'), long, default_value("intercept.yaml"))]
+    #[arg(short('f'), long, default_value("intercept.yaml"), global = true)]
     config_file: String,
+    /// Seed for the random redirection, overriding the configuration
+    #[arg(long, env = "INTERCEPT_SEED", global = true)]
+    seed: Option<u64>,
+    #[command(subcommand)]
+    mode: Option<Mode>,
     #[arg(last = true)]
     cmd: Vec<String>,
 }
 
+#[derive(Subcommand, Debug)]
+enum Mode {
+    /// Feed the results of a previous recording back to the command
+    Replay {
+        recording: PathBuf,
+        #[arg(last = true)]
+        cmd: Vec<String>,
+    },
+}
+
 fn main() {
     let args = Args::parse();
-    let conf = match Config::load(&args.config_file) {
+    let mut conf = match Config::load(&args.config_file) {
         Ok(conf) => conf,
         Err(e) => {
             println!("Error in configuration: {}", e);
             exit(1);
         }
     };
+    if let Some(seed) = args.seed {
+        conf.redirect.random.seed = seed;
+    }
     // Init logging
     let level: Level = (&conf.log.level).into();
     tracing_subscriber::fmt().with_max_level(level).init();
@@ -30,9 +48,20 @@ fn main() {
 
     debug!("Configuration: {:#?}", conf);
 
-    info!(cmd = args.cmd.join(" "), "Will run command");
+    let (cmd, recording) = match args.mode {
+        Some(Mode::Replay { recording, cmd }) => match Recording::load(&recording) {
+            Ok(recording) => (cmd, Some(recording)),
+            Err(e) => {
+                error!("couldn't load recording {}: {}", recording.display(), e);
+                exit(1)
+            }
+        },
+        None => (args.cmd, None),
+    };
 
-    if let Some(program) = args.cmd.first() {
+    info!(cmd = cmd.join(" "), "Will run command");
+
+    if let Some(program) = cmd.first() {
         let opts = match (&conf).try_into() {
             Ok(opts) => opts,
             Err(e) => {
@@ -40,7 +69,7 @@ fn main() {
                 exit(1)
             }
         };
-        let tracer = match Tracer::spawn(program, args.cmd.iter().skip(1), opts) {
+        let tracer = match Tracer::spawn(program, cmd.iter().skip(1), opts) {
             Ok(tracer) => tracer,
             Err(e) => {
                 error!("couldn't spawn command: {}", e);
@@ -48,7 +77,11 @@ fn main() {
             }
         };
         info!("command spawned");
-        if let Err(e) = tracer.run(&conf) {
+        let result = match recording {
+            Some(recording) => tracer.replay(&conf, recording),
+            None => tracer.run(&conf),
+        };
+        if let Err(e) = result {
             error!("error during command execution: {}", e);
             exit(1)
         }
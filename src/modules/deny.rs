@@ -0,0 +1,91 @@
+use std::io::Result;
+
+use tracing::info;
+
+use super::file::resolve;
+use crate::{
+    config::{DenyRule, Pattern},
+    recorder::DenyRecord,
+    tracer::{Operation, Tracee},
+    SysNum,
+};
+
+pub struct DenyManager {
+    rules: Vec<DenyRule>,
+    follow_symlinks: bool,
+}
+
+impl DenyManager {
+    pub fn new(rules: Vec<DenyRule>, follow_symlinks: bool) -> Self {
+        DenyManager {
+            rules,
+            follow_symlinks,
+        }
+    }
+
+    // Skip the syscall if a rule matches, before any other manager sees it.
+    pub fn process(
+        &self,
+        tracee: &mut Tracee,
+        operation: &Operation,
+    ) -> Result<Option<DenyRecord>> {
+        if self.rules.is_empty() {
+            return Ok(None);
+        }
+        let num = SysNum::from(tracee.registers().orig_rax);
+        let paths = resolved_paths(tracee, operation, self.follow_symlinks);
+
+        for rule in &self.rules {
+            let Some(path) = matching_path(rule.syscall, rule.path.as_ref(), num, &paths) else {
+                continue;
+            };
+
+            info!("denying {:?}: {}", num, rule.errno);
+            tracee.skip_syscall(-(rule.errno as i64) as u64)?;
+            return Ok(Some(DenyRecord {
+                syscall: format!("{:?}", num).to_lowercase(),
+                path,
+                errno: format!("{:?}", rule.errno),
+            }));
+        }
+        Ok(None)
+    }
+}
+
+pub(super) fn resolved_paths(
+    tracee: &Tracee,
+    operation: &Operation,
+    follow_symlinks: bool,
+) -> Vec<String> {
+    operation
+        .paths()
+        .into_iter()
+        .map(|path| {
+            resolve(tracee, path, follow_symlinks)
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect()
+}
+
+// Check a rule filtering on the syscall and/or the path. Returns None if the
+// rule doesn't match, otherwise the path it applies to, if any. Rules without
+// any filter never match.
+pub(super) fn matching_path(
+    syscall: Option<SysNum>,
+    pattern: Option<&Pattern>,
+    num: SysNum,
+    paths: &[String],
+) -> Option<Option<String>> {
+    if syscall.is_some_and(|syscall| syscall != num) {
+        return None;
+    }
+    match pattern {
+        Some(pattern) => paths
+            .iter()
+            .find(|path| pattern.matches(path))
+            .map(|path| Some(path.clone())),
+        None if syscall.is_none() => None,
+        None => Some(paths.first().cloned()),
+    }
+}
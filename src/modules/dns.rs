@@ -0,0 +1,357 @@
+use std::{
+    fs,
+    io::{ErrorKind, Read, Result, Write},
+    net::{IpAddr, Ipv4Addr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tempfile::NamedTempFile;
+use tracing::{debug, info, warn};
+
+use crate::config::{DnsConfig, HostEntry, Redirect};
+
+const HOSTS: &str = "/etc/hosts";
+const NSSWITCH_CONF: &str = "/etc/nsswitch.conf";
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_FORMERR: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+
+pub struct DnsManager {
+    // Generated files, removed when the manager is dropped.
+    files: Vec<(&'static str, NamedTempFile)>,
+    resolver: Option<Resolver>,
+}
+
+// DNS server answering from the configured hosts, until dropped.
+struct Resolver {
+    address: String,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl DnsManager {
+    pub fn new(config: Option<DnsConfig>) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(DnsManager {
+                files: vec![],
+                resolver: None,
+            });
+        };
+
+        // Queries go to port 53, over UDP or TCP, which is redirected to the
+        // resolver whatever the nameserver, so resolv.conf is left alone.
+        let sources = if config.resolver {
+            "files dns"
+        } else {
+            "files"
+        };
+        let files = vec![
+            (HOSTS, temp_file(&hosts(&config.hosts))?),
+            (NSSWITCH_CONF, temp_file(&nsswitch(sources))?),
+        ];
+
+        let resolver = if config.resolver {
+            Some(Resolver::start(config.hosts)?)
+        } else {
+            None
+        };
+        Ok(DnsManager { files, resolver })
+    }
+
+    // Files to redirect so that the program knows the configured hosts.
+    pub fn redirects(&self) -> Vec<(String, String)> {
+        self.files
+            .iter()
+            .map(|(from, file)| (from.to_string(), file.path().to_string_lossy().to_string()))
+            .collect()
+    }
+
+    // DNS traffic to redirect to the resolver.
+    pub fn network_redirects(&self) -> Vec<Redirect> {
+        let Some(ref resolver) = self.resolver else {
+            return vec![];
+        };
+        ["0.0.0.0/0:53", "[::/0]:53"]
+            .iter()
+            .map(|from| Redirect {
+                from: from.to_string(),
+                to: resolver.address.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Resolver {
+    fn start(hosts: Vec<HostEntry>) -> Result<Self> {
+        let (socket, listener) = bind()?;
+        // Wake up regularly to notice when the resolver is dropped.
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let address = socket.local_addr()?.to_string();
+        debug!("dns resolver on {}", address);
+        let hosts = Arc::new(hosts);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let stopped = stop.clone();
+        let udp_hosts = hosts.clone();
+        let udp = thread::spawn(move || {
+            let mut buffer = [0; 512];
+            while !stopped.load(Ordering::Relaxed) {
+                let (len, peer) = match socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        continue
+                    }
+                    Err(e) => {
+                        warn!("dns resolver failed: {}", e);
+                        return;
+                    }
+                };
+                let Some(response) = answer(&udp_hosts, &buffer[..len]) else {
+                    continue;
+                };
+                if let Err(e) = socket.send_to(&response, peer) {
+                    warn!("failed to answer dns query: {}", e);
+                }
+            }
+        });
+
+        // Queries over TCP, after a truncated answer or with `use-vc`. Each
+        // connection is served by its own thread.
+        let stopped = stop.clone();
+        let tcp = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("dns resolver failed: {}", e);
+                        continue;
+                    }
+                };
+                let hosts = hosts.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(&hosts, stream) {
+                        debug!("dns connection closed: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(Resolver {
+            address,
+            stop,
+            threads: vec![udp, tcp],
+        })
+    }
+}
+
+impl Drop for Resolver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wake the TCP thread up from accept().
+        let _ = TcpStream::connect(&self.address);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+// UDP and TCP sockets on the same loopback port.
+fn bind() -> Result<(UdpSocket, TcpListener)> {
+    let mut error = None;
+    for _ in 0..16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        match UdpSocket::bind(listener.local_addr()?) {
+            Ok(socket) => return Ok((socket, listener)),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap())
+}
+
+// Answer the queries of a TCP connection, each prefixed with its length,
+// until it is closed.
+fn serve(hosts: &[HostEntry], mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    loop {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut query = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut query)?;
+        let Some(response) = answer(hosts, &query) else {
+            continue;
+        };
+        let mut data = Vec::from((response.len() as u16).to_be_bytes());
+        data.extend_from_slice(&response);
+        stream.write_all(&data)?;
+    }
+}
+
+// Response to a DNS query, with the A or AAAA records of the configured host.
+// Other types of records are never found, and unknown hosts don't exist.
+fn answer(hosts: &[HostEntry], query: &[u8]) -> Option<Vec<u8>> {
+    if query.len() < 12 {
+        return None;
+    }
+    let id = &query[0..2];
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    // Not a query.
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+    // Authoritative answer, with recursion desired copied from the query and
+    // available.
+    let mut flags = 0x8000 | 0x0400 | (flags & 0x0100) | 0x0080;
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+
+    let question = if questions == 1 {
+        parse_question(&query[12..])
+    } else {
+        None
+    };
+    let Some((name, kind, class, end)) = question else {
+        flags |= RCODE_FORMERR;
+        let mut response = Vec::from(id);
+        response.extend_from_slice(&flags.to_be_bytes());
+        response.extend_from_slice(&[0; 8]);
+        return Some(response);
+    };
+
+    let known: Vec<IpAddr> = hosts
+        .iter()
+        .filter(|entry| entry.name.trim_end_matches('.').eq_ignore_ascii_case(&name))
+        .map(|entry| entry.address)
+        .collect();
+    let records: Vec<Vec<u8>> = known
+        .iter()
+        .filter_map(|address| match (address, kind, class) {
+            (IpAddr::V4(ip), TYPE_A, CLASS_IN) => Some(ip.octets().to_vec()),
+            (IpAddr::V6(ip), TYPE_AAAA, CLASS_IN) => Some(ip.octets().to_vec()),
+            _ => None,
+        })
+        .collect();
+    if known.is_empty() {
+        flags |= RCODE_NXDOMAIN;
+    }
+    info!(
+        "dns query for {} ({}): {} records",
+        name,
+        kind,
+        records.len()
+    );
+
+    let mut response = Vec::from(id);
+    response.extend_from_slice(&flags.to_be_bytes());
+    response.extend_from_slice(&1u16.to_be_bytes());
+    response.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0; 4]);
+    response.extend_from_slice(&query[12..12 + end]);
+    for data in records {
+        // The name points to the question.
+        response.extend_from_slice(&[0xc0, 12]);
+        response.extend_from_slice(&kind.to_be_bytes());
+        response.extend_from_slice(&class.to_be_bytes());
+        response.extend_from_slice(&0u32.to_be_bytes());
+        response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        response.extend_from_slice(&data);
+    }
+    Some(response)
+}
+
+// Name, type and class of a question, and where it ends.
+fn parse_question(data: &[u8]) -> Option<(String, u16, u16, usize)> {
+    let mut labels = Vec::new();
+    let mut offset = 0;
+    loop {
+        let len = *data.get(offset)? as usize;
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression is not expected in questions.
+        if len > 63 {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(data.get(offset..offset + len)?).to_string());
+        offset += len;
+    }
+    let kind = u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().unwrap());
+    let class = u16::from_be_bytes(data.get(offset + 2..offset + 4)?.try_into().unwrap());
+    Some((labels.join("."), kind, class, offset + 4))
+}
+
+// The host's /etc/hosts, where the configured names replace any previous
+// entry for them.
+fn hosts(entries: &[HostEntry]) -> String {
+    let content = fs::read_to_string(HOSTS).unwrap_or_default();
+    let configured = |name: &str| {
+        entries
+            .iter()
+            .any(|entry| entry.name.eq_ignore_ascii_case(name))
+    };
+    let mut hosts = String::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        if fields.len() < 2 || !fields[1..].iter().any(|name| configured(name)) {
+            hosts.push_str(line);
+            hosts.push('\n');
+            continue;
+        }
+        let names: Vec<&str> = fields[1..]
+            .iter()
+            .copied()
+            .filter(|name| !configured(name))
+            .collect();
+        if !names.is_empty() {
+            hosts.push_str(&format!("{} {}\n", fields[0], names.join(" ")));
+        }
+    }
+    for entry in entries {
+        hosts.push_str(&format!("{} {}\n", entry.address, entry.name));
+    }
+    hosts
+}
+
+// The host's /etc/nsswitch.conf, with host names looked up from `sources`
+// only.
+fn nsswitch(sources: &str) -> String {
+    let content = fs::read_to_string(NSSWITCH_CONF).unwrap_or_default();
+    let mut nsswitch = String::new();
+    let mut replaced = false;
+    for line in content.lines() {
+        if line.trim_start().starts_with("hosts:") {
+            if !replaced {
+                nsswitch.push_str(&format!("hosts: {}\n", sources));
+                replaced = true;
+            }
+            continue;
+        }
+        nsswitch.push_str(line);
+        nsswitch.push('\n');
+    }
+    if !replaced {
+        nsswitch.push_str(&format!("hosts: {}\n", sources));
+    }
+    nsswitch
+}
+
+fn temp_file(content: &str) -> Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(content.as_bytes())?;
+    file.flush()?;
+    Ok(file)
+}
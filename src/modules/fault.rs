@@ -0,0 +1,107 @@
+use std::io::Result;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use tracing::{info, warn};
+
+use super::deny::{matching_path, resolved_paths};
+use crate::{
+    config::{FaultConfig, FaultRule},
+    recorder::FaultRecord,
+    tracer::{Operation, Tracee},
+    SysNum,
+};
+
+pub struct FaultManager {
+    rules: Vec<FaultRule>,
+    // Number of matching calls seen by each rule.
+    counts: Vec<u64>,
+    rng: ChaCha20Rng,
+    follow_symlinks: bool,
+}
+
+impl FaultManager {
+    pub fn new(cfg: &FaultConfig, follow_symlinks: bool) -> Self {
+        FaultManager {
+            rules: cfg.rules.clone(),
+            counts: vec![0; cfg.rules.len()],
+            rng: ChaCha20Rng::seed_from_u64(cfg.seed),
+            follow_symlinks,
+        }
+    }
+
+    // Inject the first fault whose rule triggers. Failed syscalls are skipped,
+    // short ones still run with a smaller count.
+    pub fn process(
+        &mut self,
+        tracee: &mut Tracee,
+        operation: &Operation,
+    ) -> Result<Option<FaultRecord>> {
+        if self.rules.is_empty() {
+            return Ok(None);
+        }
+        let num = SysNum::from(tracee.registers().orig_rax);
+        let mut paths = resolved_paths(tracee, operation, self.follow_symlinks);
+        // Reads and writes match on the file behind their fd.
+        if is_io(num) {
+            if let Ok(path) = tracee.fd_path(tracee.registers().rdi) {
+                paths.push(path.to_string_lossy().to_string());
+            }
+        }
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            let Some(path) = matching_path(rule.syscall, rule.path.as_ref(), num, &paths) else {
+                continue;
+            };
+            self.counts[index] += 1;
+            if rule
+                .every
+                .is_some_and(|every| !self.counts[index].is_multiple_of(every.max(1)))
+            {
+                continue;
+            }
+            if rule
+                .probability
+                .is_some_and(|probability| !self.rng.gen_bool(probability.clamp(0.0, 1.0)))
+            {
+                continue;
+            }
+
+            let mut record = FaultRecord {
+                syscall: format!("{:?}", num).to_lowercase(),
+                path,
+                errno: None,
+                length: None,
+            };
+            if let Some(errno) = rule.errno {
+                info!("injecting {} in {:?}", errno, num);
+                tracee.skip_syscall(-(errno as i64) as u64)?;
+                record.errno = Some(format!("{:?}", errno));
+            } else if let Some(short) = rule.short {
+                if !is_io(num) {
+                    warn!("cannot shorten {:?}", num);
+                    continue;
+                }
+                let count = tracee.registers().rdx as usize;
+                if count <= short {
+                    continue;
+                }
+                info!("shortening {:?} from {} to {} bytes", num, count, short);
+                tracee.set_arg(3, short as u64)?;
+                record.length = Some(short);
+            } else {
+                continue;
+            }
+            return Ok(Some(record));
+        }
+        Ok(None)
+    }
+}
+
+// Syscalls whose third argument is a count of bytes.
+fn is_io(num: SysNum) -> bool {
+    matches!(
+        num,
+        SysNum::Read | SysNum::Write | SysNum::PRead | SysNum::PWrite
+    )
+}
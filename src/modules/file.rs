@@ -1,50 +1,71 @@
 use std::{
-    collections::HashMap,
-    io::{Error, ErrorKind, Result},
-    path::Path,
+    collections::{HashMap, VecDeque},
+    io::{Error, Result},
+    path::{Component, Path, PathBuf},
 };
 
-use tracing::info;
+use tracing::{info, warn};
 
+use super::pid::PIDManager;
 use crate::{
-    recorder::FileRecord,
+    recorder::{ChangeRecord, FileRecord, PathRecord},
     tracer::{OperationResult, Tracee},
     SysNum,
 };
 
+// Path that never exists, used to make an open fail when replaying a recording.
+const MISSING_PATH: &str = "/nonexistent/intercept/replay";
+
 pub struct FileManager {
     redirects: HashMap<String, String>,
+    follow_symlinks: bool,
+    replay: Option<HashMap<String, VecDeque<FileRecord>>>,
 }
 
 impl FileManager {
-    pub fn new(redirects: HashMap<String, String>) -> Self {
-        FileManager { redirects }
+    pub fn new(redirects: HashMap<String, String>, follow_symlinks: bool) -> Self {
+        FileManager {
+            redirects,
+            follow_symlinks,
+            replay: None,
+        }
+    }
+
+    pub fn replay(
+        redirects: HashMap<String, String>,
+        follow_symlinks: bool,
+        records: HashMap<String, VecDeque<FileRecord>>,
+    ) -> Self {
+        FileManager {
+            redirects,
+            follow_symlinks,
+            replay: Some(records),
+        }
     }
 
     pub fn process(
-        &self,
+        &mut self,
         tracee: &mut Tracee,
         path: &Path,
         num: SysNum,
         read: bool,
         write: bool,
+        pids: &mut PIDManager,
     ) -> Result<FileRecord> {
         // Maybe redirect the open syscall to a different file.
-        let absolute = match path.canonicalize() {
-            Ok(absolute) => absolute,
-            Err(_) => {
-                // TODO: use other function to normalize paths
-                // debug!("failed to canonicalize path: {:?}", path);
-                path.to_path_buf()
-            }
-        };
-
-        let absolute = absolute.to_string_lossy().to_string();
-        if let Some(dest) = self.redirects.get(&absolute) {
+        if let Some((absolute, dest)) = self.destination(tracee, path, pids)? {
             info!("redirecting open() from {} to {}", absolute, dest);
 
             // Inject the new path into the tracee's memory.
-            self.redirect(tracee, dest, num)?;
+            self.redirect(tracee, &dest, num)?;
+        }
+
+        // When replaying, make the open fail if it failed in the recording.
+        let path_str = path.to_string_lossy().to_string();
+        let replayed = self.next_replayed(&path_str);
+        if let Some(false) = replayed {
+            info!("replaying failed open() of {}", path_str);
+            self.redirect(tracee, MISSING_PATH, num)?;
         }
 
         let result = tracee.get_result()?;
@@ -59,9 +80,13 @@ impl FileManager {
             }
         };
 
+        if replayed == Some(true) && !success {
+            warn!("open({}) succeeded in the recording, but failed", path_str);
+        }
+
         // Let the syscall run.
         let record = FileRecord {
-            path: path.to_string_lossy().to_string(),
+            path: path_str,
             success,
             read,
             write,
@@ -69,20 +94,199 @@ impl FileManager {
         Ok(record)
     }
 
-    fn redirect(&self, tracee: &mut Tracee, dest: &str, num: SysNum) -> Result<()> {
-        let mem = tracee.write_string(dest)?;
+    // Lookups follow the same redirections as opens, so that metadata and
+    // existence checks agree with what an open would see.
+    pub fn lookup(
+        &mut self,
+        tracee: &mut Tracee,
+        path: &Path,
+        num: SysNum,
+        pids: &mut PIDManager,
+    ) -> Result<PathRecord> {
+        if let Some((absolute, dest)) = self.destination(tracee, path, pids)? {
+            info!("redirecting {:?} from {} to {}", num, absolute, dest);
+            self.redirect(tracee, &dest, num)?;
+        }
 
-        let arg = match num {
-            SysNum::Open => 1,
-            SysNum::OpenAt => 2,
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "invalid sysnum in Open operation",
-                ))
+        let success = match tracee.get_result()? {
+            OperationResult::Success(_) => {
+                info!("{:?}({})", num, path.display());
+                true
+            }
+            OperationResult::Error(errno) => {
+                info!("{:?}({}): {}", num, path.display(), errno);
+                false
+            }
+        };
+
+        Ok(PathRecord {
+            syscall: format!("{:?}", num).to_lowercase(),
+            path: path.to_string_lossy().to_string(),
+            success,
+        })
+    }
+
+    pub fn change(
+        &mut self,
+        tracee: &mut Tracee,
+        paths: &[(u8, PathBuf)],
+        num: SysNum,
+        target: &Option<String>,
+        pids: &mut PIDManager,
+    ) -> Result<ChangeRecord> {
+        for (arg, path) in paths {
+            if let Some((absolute, dest)) = self.destination(tracee, path, pids)? {
+                info!("redirecting {:?} from {} to {}", num, absolute, dest);
+                self.redirect_arg(tracee, &dest, *arg)?;
+            }
+        }
+
+        // Paths are recorded absolute, without following the last symlink
+        // since most of these syscalls act on the link itself.
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|(_, path)| resolve(tracee, path, false).to_string_lossy().to_string())
+            .collect();
+        let success = match tracee.get_result()? {
+            OperationResult::Success(_) => {
+                info!("{:?}({})", num, paths.join(", "));
+                true
+            }
+            OperationResult::Error(errno) => {
+                info!("{:?}({}): {}", num, paths.join(", "), errno);
+                false
+            }
+        };
+
+        Ok(ChangeRecord {
+            syscall: format!("{:?}", num).to_lowercase(),
+            paths,
+            target: target.clone(),
+            success,
+        })
+    }
+
+    // Handles can't be redirected, but the opened file can still be recorded.
+    // Failed opens are not, since there is no path to record.
+    pub fn open_by_handle(
+        &mut self,
+        tracee: &mut Tracee,
+        read: bool,
+        write: bool,
+    ) -> Result<Option<FileRecord>> {
+        let fd = match tracee.get_result()? {
+            OperationResult::Success(fd) => fd,
+            OperationResult::Error(errno) => {
+                info!("open_by_handle_at(): {}", errno);
+                return Ok(None);
+            }
+        };
+        let path = tracee.fd_path(fd as u64)?;
+        info!("open_by_handle_at({})", path.display());
+        Ok(Some(FileRecord {
+            path: path.to_string_lossy().to_string(),
+            success: true,
+            read,
+            write,
+        }))
+    }
+
+    // The absolute path and where to redirect it, if anywhere. Paths under
+    // /proc may name virtual PIDs, and never follow symlinks since the tracer
+    // would see its own /proc/self.
+    fn destination(
+        &self,
+        tracee: &Tracee,
+        path: &Path,
+        pids: &mut PIDManager,
+    ) -> Result<Option<(String, String)>> {
+        let absolute = resolve(tracee, path, self.follow_symlinks);
+        let absolute = absolute.to_string_lossy().to_string();
+        if let Some(dest) = self.redirects.get(&absolute) {
+            return Ok(Some((absolute, dest.clone())));
+        }
+        let absolute = resolve(tracee, path, false);
+        Ok(pids
+            .proc_path(tracee, &absolute)?
+            .map(|dest| (absolute.to_string_lossy().to_string(), dest)))
+    }
+
+    fn next_replayed(&mut self, path: &str) -> Option<bool> {
+        let records = self.replay.as_mut()?;
+        match records.get_mut(path).and_then(|queue| queue.pop_front()) {
+            Some(record) => Some(record.success),
+            None => {
+                warn!("open({}) is not in the recording", path);
+                None
             }
+        }
+    }
+
+    fn redirect(&self, tracee: &mut Tracee, dest: &str, num: SysNum) -> Result<()> {
+        let arg = match num {
+            SysNum::Open
+            | SysNum::Creat
+            | SysNum::Stat
+            | SysNum::LStat
+            | SysNum::Access
+            | SysNum::ReadLink
+            | SysNum::StatFS
+            | SysNum::GetXAttr
+            | SysNum::LGetXAttr
+            | SysNum::ListXAttr
+            | SysNum::LListXAttr => 1,
+            SysNum::OpenAt
+            | SysNum::OpenAt2
+            | SysNum::NameToHandleAt
+            | SysNum::NewFstatAt
+            | SysNum::StatX
+            | SysNum::FAccessAt
+            | SysNum::FAccessAt2
+            | SysNum::ReadLinkAt => 2,
+            _ => return Err(Error::other("invalid sysnum in file operation")),
         };
+        self.redirect_arg(tracee, dest, arg)
+    }
+
+    fn redirect_arg(&self, tracee: &mut Tracee, dest: &str, arg: u8) -> Result<()> {
+        let mem = tracee.write_string(dest)?;
         tracee.set_arg(arg, mem)?;
         Ok(())
     }
 }
+
+// Absolute path as seen by the tracee, normalized lexically.
+pub fn resolve(tracee: &Tracee, path: &Path, follow_symlinks: bool) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        normalize(path)
+    } else {
+        match tracee.cwd() {
+            Ok(cwd) => normalize(&cwd.join(path)),
+            Err(e) => {
+                warn!("failed to read the working directory: {}", e);
+                normalize(path)
+            }
+        }
+    };
+    if follow_symlinks {
+        if let Ok(canonical) = absolute.canonicalize() {
+            return canonical;
+        }
+    }
+    absolute
+}
+
+// Remove `.` and `..` components without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,143 @@
+use std::{
+    fs,
+    io::{Result, Write},
+};
+
+use nix::errno::Errno;
+use tempfile::NamedTempFile;
+use tracing::info;
+
+use crate::{
+    config::IdentityConfig,
+    tracer::{OperationResult, Tracee},
+    SysNum,
+};
+
+const PASSWD: &str = "/etc/passwd";
+
+pub struct IdentityManager {
+    config: Option<IdentityConfig>,
+    // Copy of /etc/passwd with an entry for the configured user, removed when
+    // the manager is dropped.
+    passwd: Option<NamedTempFile>,
+}
+
+impl IdentityManager {
+    pub fn new(config: Option<IdentityConfig>) -> Result<Self> {
+        let passwd = match config {
+            Some(IdentityConfig {
+                uid,
+                gid,
+                username: Some(ref username),
+                ..
+            }) => Some(passwd(uid, gid, username)?),
+            _ => None,
+        };
+        Ok(IdentityManager { config, passwd })
+    }
+
+    // Files to redirect so that the configured user has a name.
+    pub fn redirects(&self) -> Option<(String, String)> {
+        let passwd = self.passwd.as_ref()?;
+        Some((
+            PASSWD.to_string(),
+            passwd.path().to_string_lossy().to_string(),
+        ))
+    }
+
+    pub fn process(&self, tracee: &mut Tracee, num: SysNum) -> Result<()> {
+        let Some(ref config) = self.config else {
+            return Ok(());
+        };
+        // The real syscall would fail if the host user is in more groups.
+        if num == SysNum::GetGroups {
+            return self.groups(tracee, config);
+        }
+        if num == SysNum::GetResUID || num == SysNum::GetResGID {
+            return self.ids(tracee, num, config);
+        }
+        let OperationResult::Success(_) = tracee.get_result()? else {
+            return Ok(());
+        };
+        match num {
+            SysNum::GetUID | SysNum::GetEUID => {
+                info!("overriding {:?}", num);
+                tracee.set_result(config.uid as u64)?;
+            }
+            SysNum::GetGID | SysNum::GetEGID => {
+                info!("overriding {:?}", num);
+                tracee.set_result(config.gid as u64)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // The real, effective and saved ids, written without running the syscall.
+    fn ids(&self, tracee: &mut Tracee, num: SysNum, config: &IdentityConfig) -> Result<()> {
+        info!("overriding {:?}", num);
+        let id = if num == SysNum::GetResUID {
+            config.uid
+        } else {
+            config.gid
+        };
+        let registers = tracee.registers();
+        for addr in [registers.rdi, registers.rsi, registers.rdx] {
+            if tracee.write_bytes(addr, &id.to_ne_bytes()).is_err() {
+                return tracee.skip_syscall(-(Errno::EFAULT as i64) as u64);
+            }
+        }
+        tracee.skip_syscall(0)
+    }
+
+    // The configured groups, written without running the syscall.
+    fn groups(&self, tracee: &mut Tracee, config: &IdentityConfig) -> Result<()> {
+        info!("overriding {:?}", SysNum::GetGroups);
+        let groups = if config.groups.is_empty() {
+            vec![config.gid]
+        } else {
+            config.groups.clone()
+        };
+        // A null size asks for the number of groups only.
+        let size = tracee.registers().rdi as i32;
+        if size < 0 || (size > 0 && (size as usize) < groups.len()) {
+            return tracee.skip_syscall(-(Errno::EINVAL as i64) as u64);
+        }
+        if size > 0 {
+            let data: Vec<u8> = groups.iter().flat_map(|gid| gid.to_ne_bytes()).collect();
+            // Bad pointers fail the syscall, not the trace.
+            if tracee.write_bytes(tracee.registers().rsi, &data).is_err() {
+                return tracee.skip_syscall(-(Errno::EFAULT as i64) as u64);
+            }
+        }
+        tracee.skip_syscall(groups.len() as u64)
+    }
+}
+
+// Write a copy of /etc/passwd where the user, or any previous user with the
+// same name or uid, is replaced. Its home and shell are kept if it had an
+// entry.
+fn passwd(uid: u32, gid: u32, username: &str) -> Result<NamedTempFile> {
+    let content = fs::read_to_string(PASSWD).unwrap_or_default();
+    let mut home = String::from("/");
+    let mut shell = String::from("/bin/sh");
+    let mut file = NamedTempFile::new()?;
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() == 7 && (fields[0] == username || fields[2] == uid.to_string()) {
+            if fields[2] == uid.to_string() {
+                home = fields[5].to_string();
+                shell = fields[6].to_string();
+            }
+            continue;
+        }
+        writeln!(file, "{}", line)?;
+    }
+    writeln!(
+        file,
+        "{}:x:{}:{}:{}:{}:{}",
+        username, uid, gid, username, home, shell
+    )?;
+    file.flush()?;
+    Ok(file)
+}
@@ -1,9 +1,23 @@
+mod deny;
+mod dns;
+mod fault;
 mod file;
+mod identity;
+mod net;
 mod pid;
 mod rand;
+mod stat;
 mod time;
+mod uname;
 
+pub use deny::DenyManager;
+pub use dns::DnsManager;
+pub use fault::FaultManager;
 pub use file::FileManager;
+pub use identity::IdentityManager;
+pub use net::NetManager;
 pub use pid::PIDManager;
 pub use rand::RandomManager;
+pub use stat::StatManager;
 pub use time::TimeManager;
+pub use uname::UnameManager;
@@ -0,0 +1,606 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Error, ErrorKind, Result},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
+};
+
+use nix::errno::Errno;
+use nix::libc::{
+    AF_INET, AF_INET6, AF_NETLINK, AF_PACKET, AF_UNIX, FD_CLOEXEC, F_GETFD, F_GETFL, O_CLOEXEC,
+    O_NONBLOCK, SOCK_DGRAM, SOCK_NONBLOCK, SOCK_RAW, SOCK_SEQPACKET, SOCK_STREAM, SOL_SOCKET,
+    SO_DOMAIN, SO_TYPE, UIO_MAXIOV,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::Redirect,
+    recorder::NetRecord,
+    tracer::{Memory, OperationResult, Tracee},
+    SysNum,
+};
+
+// Size of struct mmsghdr, a struct msghdr followed by the length sent. The
+// name and its length start both structs.
+const MMSGHDR_SIZE: u64 = 64;
+
+// Results of syscalls interrupted by a signal, which the kernel runs again
+// with the same arguments: ERESTARTSYS to ERESTART_RESTARTBLOCK.
+const RESTART_ERRNOS: std::ops::RangeInclusive<i64> = 512..=516;
+
+// Decoded struct sockaddr.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketAddress {
+    Inet(SocketAddr),
+    Unix(String),
+    // Linux specific sockets outside of the file system.
+    Abstract(String),
+    Unnamed,
+    Other(u16),
+}
+
+// Outcome of the redirections and the offline mode for an address, with the
+// address and length of the redirected one.
+enum Route {
+    Keep,
+    Redirect(u64, u64),
+    Block,
+}
+
+// Addresses matched by a redirection: a range of IPs, and maybe a port.
+#[derive(Debug)]
+struct Destination {
+    ip: IpAddr,
+    prefix: u8,
+    port: Option<u16>,
+}
+
+// A syscall let through at its entry, completed when it returns so that the
+// tracer doesn't wait on it.
+struct Pending {
+    num: SysNum,
+    record: NetRecord,
+    // Message headers with a redirected name, and their original bytes.
+    patched: Vec<(u64, Vec<u8>)>,
+    // Redirected addresses, which must outlive the syscall.
+    memory: Vec<Memory>,
+    // Interrupted, and about to run again as already redirected.
+    restarting: bool,
+}
+
+pub struct NetManager {
+    // Redirections in the order of the configuration. Targets without a port
+    // keep the original one.
+    redirects: Vec<(Destination, SocketAddress)>,
+    // Fail connections to anything but the local host.
+    offline: bool,
+    // Whether syscalls are recorded, blocked ones always are.
+    record: bool,
+    // By thread.
+    pending: HashMap<i32, Pending>,
+}
+
+impl NetManager {
+    pub fn new(redirects: &[Redirect], offline: bool, record: bool) -> Result<Self> {
+        let mut parsed = Vec::new();
+        for redirect in redirects {
+            let to = parse_target(&redirect.to)?;
+            for from in parse_destinations(&redirect.from)? {
+                debug!(?from, %to, "network redirection");
+                parsed.push((from, to.clone()));
+            }
+        }
+        Ok(NetManager {
+            redirects: parsed,
+            offline,
+            record,
+            pending: HashMap::new(),
+        })
+    }
+
+    // Redirect or block the syscall at its entry. Only blocked ones are
+    // recorded here, the others once they return.
+    pub fn process(&mut self, tracee: &mut Tracee, num: SysNum) -> Result<Option<NetRecord>> {
+        if let Some(pending) = self.pending.get(&tracee.pid()) {
+            if pending.restarting && pending.num == num {
+                debug!(?num, "restarting network syscall");
+                return Ok(None);
+            }
+        }
+        let registers = tracee.registers();
+        let mut record = NetRecord {
+            syscall: format!("{:?}", num).to_lowercase(),
+            family: None,
+            kind: None,
+            address: None,
+            redirect: None,
+            success: false,
+            errno: None,
+            blocked: false,
+        };
+        let mut patched = Vec::new();
+        match num {
+            SysNum::Socket => {
+                record.family = Some(family(registers.rdi as i32));
+                record.kind = Some(kind(registers.rsi as i32));
+            }
+            SysNum::Bind => {
+                record.address = read_address(tracee, registers.rsi, registers.rdx)
+                    .map(|address| address.to_string());
+            }
+            SysNum::Connect | SysNum::SendTo => {
+                let (arg, addr, len) = match num {
+                    SysNum::Connect => (2, registers.rsi, registers.rdx),
+                    _ => (5, registers.r8, registers.r9),
+                };
+                let address = read_address(tracee, addr, len);
+                record.address = address.as_ref().map(|address| address.to_string());
+                match self.route(tracee, num, registers.rdi, address, &mut record)? {
+                    Route::Keep => {}
+                    Route::Redirect(addr, len) => {
+                        tracee.set_arg(arg, addr)?;
+                        tracee.set_arg(arg + 1, len)?;
+                    }
+                    Route::Block => return self.block(tracee, record).map(Some),
+                }
+            }
+            SysNum::SendMsg | SysNum::SendMMsg => {
+                let count = match num {
+                    SysNum::SendMsg => 1,
+                    _ => registers.rdx.min(UIO_MAXIOV as u64),
+                };
+                // Redirected names are patched in the program's headers, and
+                // restored once sent.
+                for index in 0..count {
+                    let header = registers.rsi + index * MMSGHDR_SIZE;
+                    let name = tracee.read_memory(header, 12)?;
+                    let addr = u64::from_ne_bytes(name[0..8].try_into().unwrap());
+                    let len = u32::from_ne_bytes(name[8..12].try_into().unwrap());
+                    let address = read_address(tracee, addr, len as u64);
+                    if record.address.is_none() {
+                        record.address = address.as_ref().map(|address| address.to_string());
+                    }
+                    match self.route(tracee, num, registers.rdi, address, &mut record)? {
+                        Route::Keep => {}
+                        Route::Redirect(addr, len) => {
+                            let mut data = Vec::from(addr.to_ne_bytes());
+                            data.extend_from_slice(&(len as u32).to_ne_bytes());
+                            tracee.write_bytes(header, &data)?;
+                            patched.push((header, name));
+                        }
+                        Route::Block => {
+                            for (header, name) in patched {
+                                tracee.write_bytes(header, &name)?;
+                            }
+                            return self.block(tracee, record).map(Some);
+                        }
+                    }
+                }
+            }
+            _ => unreachable!("unexpected network syscall {:?}", num),
+        }
+        if record.redirect.is_none() && !self.record {
+            return Ok(None);
+        }
+        let pending = Pending {
+            num,
+            record,
+            patched,
+            memory: tracee.keep_memory(),
+            restarting: false,
+        };
+        self.pending.insert(tracee.pid(), pending);
+        Ok(None)
+    }
+
+    // Record a syscall let through by `process` once it returns, and undo its
+    // redirection.
+    pub fn result(&mut self, tracee: &mut Tracee, num: SysNum) -> Result<Option<NetRecord>> {
+        let Some(mut pending) = self.pending.remove(&tracee.pid()) else {
+            return Ok(None);
+        };
+        if pending.num != num {
+            // The thread died in the syscall, and its id was reused.
+            debug!(?num, "discarding {:?}", pending.num);
+            return Ok(None);
+        }
+        if RESTART_ERRNOS.contains(&-(tracee.registers().rax as i64)) {
+            pending.restarting = true;
+            self.pending.insert(tracee.pid(), pending);
+            return Ok(None);
+        }
+        for (header, name) in &pending.patched {
+            tracee.write_bytes(*header, name)?;
+        }
+        tracee.release_memory(pending.memory);
+        self.outcome(tracee, pending.record).map(Some)
+    }
+
+    // Connections are only known once accepted, the peer address is read
+    // from the syscall result.
+    pub fn accept(&self, tracee: &mut Tracee, num: SysNum) -> Result<NetRecord> {
+        let registers = tracee.registers();
+        let mut record = NetRecord {
+            syscall: format!("{:?}", num).to_lowercase(),
+            family: None,
+            kind: None,
+            address: None,
+            redirect: None,
+            success: false,
+            errno: None,
+            blocked: false,
+        };
+        record = self.outcome(tracee, record)?;
+        if record.success && registers.rsi != 0 && registers.rdx != 0 {
+            let len = tracee.read_memory(registers.rdx, 4)?;
+            let len = u32::from_ne_bytes(len[0..4].try_into().unwrap());
+            record.address =
+                read_address(tracee, registers.rsi, len as u64).map(|address| address.to_string());
+        }
+        Ok(record)
+    }
+
+    fn destination(&self, address: SocketAddr) -> Option<SocketAddress> {
+        let (_, to) = self
+            .redirects
+            .iter()
+            .find(|(from, _)| from.matches(address))?;
+        Some(match to {
+            SocketAddress::Inet(to) if to.port() == 0 => {
+                SocketAddress::Inet(SocketAddr::new(to.ip(), address.port()))
+            }
+            to => to.clone(),
+        })
+    }
+
+    // Where a destination address goes: redirected, blocked by the offline
+    // mode, or left alone.
+    fn route(
+        &self,
+        tracee: &mut Tracee,
+        num: SysNum,
+        fd: u64,
+        address: Option<SocketAddress>,
+        record: &mut NetRecord,
+    ) -> Result<Route> {
+        let Some(SocketAddress::Inet(address)) = address else {
+            return Ok(Route::Keep);
+        };
+        let mut route = Route::Keep;
+        let mut target = address;
+        if let Some(dest) = self.destination(address) {
+            info!("redirecting {:?} from {} to {}", num, address, dest);
+            let (addr, len) = self.redirect(tracee, fd, &dest)?;
+            route = Route::Redirect(addr, len);
+            record.redirect.get_or_insert(dest.to_string());
+            match dest {
+                SocketAddress::Inet(dest) => target = dest,
+                _ => return Ok(route),
+            }
+        }
+        if self.offline && !is_local(target.ip()) {
+            warn!("blocking {:?} to {}", num, target);
+            return Ok(Route::Block);
+        }
+        Ok(route)
+    }
+
+    // Write `dest` in the tracee, returning its address and length. The
+    // socket is replaced by one of the right family if needed.
+    fn redirect(&self, tracee: &mut Tracee, fd: u64, dest: &SocketAddress) -> Result<(u64, u64)> {
+        if let Err(e) = self.replace_socket(tracee, fd, dest.family()) {
+            warn!("failed to replace socket {}: {}", fd, e);
+        }
+        let data = dest.encode();
+        let addr = tracee.write_data(&data)?;
+        Ok((addr, data.len() as u64))
+    }
+
+    fn block(&self, tracee: &mut Tracee, mut record: NetRecord) -> Result<NetRecord> {
+        tracee.skip_syscall(-(Errno::ENETUNREACH as i64) as u64)?;
+        record.errno = Some(format!("{:?}", Errno::ENETUNREACH));
+        record.blocked = true;
+        Ok(record)
+    }
+
+    // Swap the socket for a new one of another family, with the same type and
+    // flags, by injecting syscalls.
+    fn replace_socket(&self, tracee: &mut Tracee, fd: u64, family: i32) -> Result<()> {
+        // An int option and its length.
+        let option = tracee.write_data(&[0, 0, 0, 0, 4, 0, 0, 0])?;
+        let get_option = |tracee: &mut Tracee, name: i32| -> Result<i32> {
+            tracee.send_syscall(
+                SysNum::GetSockOpt,
+                fd,
+                SOL_SOCKET as u64,
+                name as u64,
+                option,
+                option + 4,
+                0,
+            )?;
+            let data = tracee.read_memory(option, 4)?;
+            Ok(i32::from_ne_bytes(data[0..4].try_into().unwrap()))
+        };
+        if get_option(tracee, SO_DOMAIN)? == family {
+            return Ok(());
+        }
+        let mut kind = get_option(tracee, SO_TYPE)?;
+        if tracee.send_syscall(SysNum::Fcntl, fd, F_GETFL as u64, 0, 0, 0, 0)? as i32 & O_NONBLOCK
+            != 0
+        {
+            kind |= SOCK_NONBLOCK;
+        }
+        let cloexec = tracee.send_syscall(SysNum::Fcntl, fd, F_GETFD as u64, 0, 0, 0, 0)? as i32
+            & FD_CLOEXEC
+            != 0;
+
+        info!(
+            "replacing socket {} with a {} one",
+            fd,
+            self::family(family)
+        );
+        let new = tracee.send_syscall(SysNum::Socket, family as u64, kind as u64, 0, 0, 0, 0)?;
+        let flags = if cloexec { O_CLOEXEC } else { 0 };
+        let result = tracee.send_syscall(SysNum::Dup3, new, fd, flags as u64, 0, 0, 0);
+        tracee.send_syscall(SysNum::Close, new, 0, 0, 0, 0, 0)?;
+        result.map(|_| ())
+    }
+
+    fn outcome(&self, tracee: &mut Tracee, mut record: NetRecord) -> Result<NetRecord> {
+        let address = record.address.clone().unwrap_or_default();
+        match tracee.get_result()? {
+            OperationResult::Success(_) => {
+                info!("{}({})", record.syscall, address);
+                record.success = true;
+            }
+            OperationResult::Error(errno) => {
+                info!("{}({}): {}", record.syscall, address, errno);
+                record.errno = Some(format!("{:?}", errno));
+            }
+        }
+        Ok(record)
+    }
+}
+
+pub fn read_address(tracee: &Tracee, addr: u64, len: u64) -> Option<SocketAddress> {
+    if addr == 0 || len < 2 {
+        return None;
+    }
+    // Nothing is larger than struct sockaddr_storage.
+    let data = match tracee.read_memory(addr, len.min(128) as usize) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("failed to read socket address: {}", e);
+            return None;
+        }
+    };
+    SocketAddress::decode(&data)
+}
+
+impl SocketAddress {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let family = u16::from_ne_bytes(data.get(0..2)?.try_into().unwrap());
+        let port = || Some(u16::from_be_bytes(data.get(2..4)?.try_into().unwrap()));
+        let address = match family as i32 {
+            AF_INET => {
+                let ip: [u8; 4] = data.get(4..8)?.try_into().unwrap();
+                SocketAddress::Inet(SocketAddrV4::new(Ipv4Addr::from(ip), port()?).into())
+            }
+            AF_INET6 => {
+                let flowinfo = u32::from_be_bytes(data.get(4..8)?.try_into().unwrap());
+                let ip: [u8; 16] = data.get(8..24)?.try_into().unwrap();
+                let scope = u32::from_ne_bytes(data.get(24..28)?.try_into().unwrap());
+                SocketAddress::Inet(
+                    SocketAddrV6::new(Ipv6Addr::from(ip), port()?, flowinfo, scope).into(),
+                )
+            }
+            AF_UNIX => match &data[2..] {
+                [] => SocketAddress::Unnamed,
+                [0, name @ ..] => {
+                    SocketAddress::Abstract(String::from_utf8_lossy(name).to_string())
+                }
+                path => {
+                    let end = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+                    SocketAddress::Unix(String::from_utf8_lossy(&path[..end]).to_string())
+                }
+            },
+            _ => SocketAddress::Other(family),
+        };
+        Some(address)
+    }
+
+    fn family(&self) -> i32 {
+        match self {
+            SocketAddress::Inet(SocketAddr::V4(_)) => AF_INET,
+            SocketAddress::Inet(SocketAddr::V6(_)) => AF_INET6,
+            SocketAddress::Unix(_) | SocketAddress::Abstract(_) | SocketAddress::Unnamed => AF_UNIX,
+            SocketAddress::Other(family) => *family as i32,
+        }
+    }
+
+    // Build a struct sockaddr.
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::from((self.family() as u16).to_ne_bytes());
+        match self {
+            SocketAddress::Inet(SocketAddr::V4(address)) => {
+                data.extend_from_slice(&address.port().to_be_bytes());
+                data.extend_from_slice(&address.ip().octets());
+                data.extend_from_slice(&[0; 8]);
+            }
+            SocketAddress::Inet(SocketAddr::V6(address)) => {
+                data.extend_from_slice(&address.port().to_be_bytes());
+                data.extend_from_slice(&address.flowinfo().to_be_bytes());
+                data.extend_from_slice(&address.ip().octets());
+                data.extend_from_slice(&address.scope_id().to_ne_bytes());
+            }
+            SocketAddress::Unix(path) => {
+                data.extend_from_slice(path.as_bytes());
+                data.push(0);
+            }
+            SocketAddress::Abstract(name) => {
+                data.push(0);
+                data.extend_from_slice(name.as_bytes());
+            }
+            SocketAddress::Unnamed | SocketAddress::Other(_) => {}
+        }
+        data
+    }
+}
+
+impl Destination {
+    fn matches(&self, address: SocketAddr) -> bool {
+        if self.port.is_some_and(|port| port != address.port()) {
+            return false;
+        }
+        // IPv6 sockets reach IPv4 hosts through mapped addresses.
+        match (self.ip, address.ip().to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// Loopback addresses, and unspecified ones which also reach the local host.
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_unspecified(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.is_loopback() || ip.is_unspecified(),
+            None => ip.is_loopback() || ip.is_unspecified(),
+        },
+    }
+}
+
+// `host:port`, `ip:port`, `[ipv6]:port` or a CIDR range such as `10.0.0.0/8`
+// or `[fd00::/8]:443`. The port is optional, and host names are resolved once.
+fn parse_destinations(spec: &str) -> Result<Vec<Destination>> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid network redirection: {}", spec),
+        )
+    };
+    let (host, port) = match spec.strip_prefix('[') {
+        Some(rest) => {
+            let (host, port) = rest.split_once(']').ok_or_else(invalid)?;
+            (host, port.strip_prefix(':'))
+        }
+        // Bare IPv6 addresses have no port.
+        None if spec.matches(':').count() > 1 => (spec, None),
+        None => match spec.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (spec, None),
+        },
+    };
+    let port = match port {
+        Some(port) => Some(port.parse().map_err(|_| invalid())?),
+        None => None,
+    };
+
+    if let Some((ip, prefix)) = host.split_once('/') {
+        let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+        let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+        if prefix > if ip.is_ipv4() { 32 } else { 128 } {
+            return Err(invalid());
+        }
+        // Mapped ranges are matched as the IPv4 ones they cover.
+        let (ip, prefix) = match ip {
+            IpAddr::V6(v6) if prefix >= 96 => match v6.to_ipv4_mapped() {
+                Some(v4) => (IpAddr::V4(v4), prefix - 96),
+                None => (ip, prefix),
+            },
+            _ => (ip, prefix),
+        };
+        return Ok(vec![Destination { ip, prefix, port }]);
+    }
+    let ips = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => (host, 0)
+            .to_socket_addrs()
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("cannot resolve {} in a network redirection: {}", host, e),
+                )
+            })?
+            .map(|address| address.ip())
+            .collect(),
+    };
+    Ok(ips
+        .into_iter()
+        .map(|ip| ip.to_canonical())
+        .map(|ip| Destination {
+            ip,
+            prefix: if ip.is_ipv4() { 32 } else { 128 },
+            port,
+        })
+        .collect())
+}
+
+// `ip:port`, `ip` to keep the port, or the path of a unix socket, starting
+// with `@` for abstract ones.
+fn parse_target(spec: &str) -> Result<SocketAddress> {
+    if spec.starts_with('/') {
+        return Ok(SocketAddress::Unix(spec.to_string()));
+    }
+    if let Some(name) = spec.strip_prefix('@') {
+        return Ok(SocketAddress::Abstract(name.to_string()));
+    }
+    if let Ok(address) = spec.parse::<SocketAddr>() {
+        return Ok(SocketAddress::Inet(address));
+    }
+    if let Ok(ip) = spec.trim_matches(['[', ']']).parse::<IpAddr>() {
+        return Ok(SocketAddress::Inet(SocketAddr::new(ip, 0)));
+    }
+    spec.to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .map(SocketAddress::Inet)
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid network redirection target: {}", spec),
+            )
+        })
+}
+
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocketAddress::Inet(address) => write!(f, "{}", address),
+            SocketAddress::Unix(path) => write!(f, "{}", path),
+            SocketAddress::Abstract(name) => write!(f, "@{}", name),
+            SocketAddress::Unnamed => write!(f, "unnamed"),
+            SocketAddress::Other(family) => write!(f, "{}", self::family(*family as i32)),
+        }
+    }
+}
+
+fn family(family: i32) -> String {
+    match family {
+        AF_UNIX => "unix".to_string(),
+        AF_INET => "inet".to_string(),
+        AF_INET6 => "inet6".to_string(),
+        AF_NETLINK => "netlink".to_string(),
+        AF_PACKET => "packet".to_string(),
+        _ => format!("family {}", family),
+    }
+}
+
+fn kind(kind: i32) -> String {
+    // The type also carries SOCK_NONBLOCK and SOCK_CLOEXEC.
+    match kind & 0xf {
+        SOCK_STREAM => "stream".to_string(),
+        SOCK_DGRAM => "dgram".to_string(),
+        SOCK_RAW => "raw".to_string(),
+        SOCK_SEQPACKET => "seqpacket".to_string(),
+        kind => format!("type {}", kind),
+    }
+}
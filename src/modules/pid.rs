@@ -1,6 +1,12 @@
-use std::io::{Error, ErrorKind, Result};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::{Error, Result, Write},
+    path::{Path, PathBuf},
+};
 
-use tracing::{error, info, warn};
+use tempfile::NamedTempFile;
+use tracing::{debug, error, info, warn};
 
 use crate::{
     recorder::PIDRecord,
@@ -8,8 +14,28 @@ use crate::{
     SysNum,
 };
 
+// waitid() id types.
+const P_PID: u64 = 1;
+const P_PGID: u64 = 2;
+// Offset of si_pid in siginfo_t.
+const SI_PID: u64 = 16;
+// waitid() option leaving the child waitable.
+const WNOWAIT: u64 = 0x0100_0000;
+
 pub struct PIDManager {
-    pid: Option<u32>,
+    // Virtual PID of the first process, the others are numbered after it.
+    base: Option<u32>,
+    // Virtual PIDs and TIDs, by real ones.
+    pids: HashMap<i32, u32>,
+    // Real PIDs and TIDs, by virtual ones.
+    reals: HashMap<u32, i32>,
+    // Processes that exited, until their parent reaps them, and threads.
+    exited: HashMap<i32, u32>,
+    // Virtual PID of the next process or thread.
+    next: u32,
+    // Copy of the last /proc status or stat file opened, with virtual PIDs.
+    proc_file: Option<NamedTempFile>,
+    replay: Option<VecDeque<PIDRecord>>,
 }
 
 impl PIDManager {
@@ -17,10 +43,46 @@ impl PIDManager {
         if pid.is_some() {
             warn!("Caution: PID overriding is still experimental");
         }
-        PIDManager { pid }
+        PIDManager {
+            base: pid,
+            pids: HashMap::new(),
+            reals: HashMap::new(),
+            exited: HashMap::new(),
+            next: pid.unwrap_or(0),
+            proc_file: None,
+            replay: None,
+        }
+    }
+
+    pub fn replay(records: VecDeque<PIDRecord>) -> Self {
+        PIDManager {
+            base: None,
+            pids: HashMap::new(),
+            reals: HashMap::new(),
+            exited: HashMap::new(),
+            next: 0,
+            proc_file: None,
+            replay: Some(records),
+        }
+    }
+
+    // Give the next virtual PID to a new process or thread, in the order
+    // they are created.
+    pub fn spawn(&mut self, pid: i32) {
+        if self.base.is_some() {
+            self.virtual_pid(pid);
+        }
+    }
+
+    // Keep the virtual PID of a process that exited until it is reaped, its
+    // parent may still wait for it or signal it.
+    pub fn exit(&mut self, pid: i32) {
+        if let Some(virt) = self.pids.remove(&pid) {
+            self.exited.insert(pid, virt);
+        }
     }
 
-    pub fn process(&self, tracee: &mut Tracee, num: SysNum) -> Result<PIDRecord> {
+    pub fn process(&mut self, tracee: &mut Tracee, num: SysNum) -> Result<PIDRecord> {
         let result = match tracee.get_result()? {
             OperationResult::Success(pid) => {
                 info!("getpid({}, syscall={:?})", pid, num);
@@ -29,23 +91,262 @@ impl PIDManager {
             OperationResult::Error(errno) => {
                 // This should never happen
                 error!("getpid returned an error: {}", errno);
-                return Err(Error::new(ErrorKind::Other, "getpid returned an error"));
+                return Err(Error::other("getpid returned an error"));
             }
         };
 
-        // If overwriting result is active
-        if let Some(pid) = self.pid {
+        // Replayed values take precedence over the overriding ones.
+        let syscall = format!("{:?}", num).to_lowercase();
+        if let Some(records) = self.replay.as_mut() {
+            // Calls of other syscalls may have been reordered, but their
+            // values are never swapped.
+            let index = records
+                .iter()
+                .position(|record| record.syscall.is_empty() || record.syscall == syscall);
+            match index.and_then(|index| records.remove(index)) {
+                Some(record) => {
+                    if index != Some(0) {
+                        warn!("{:?} was recorded later, replaying anyway", num);
+                    }
+                    info!("replaying {:?}", num);
+                    tracee.set_result(record.pid as u64)?;
+                }
+                None => warn!("{:?} is not in the recording", num),
+            }
+        } else if self.base.is_some() {
             match num {
-                SysNum::GetPID => {
-                    info!("overriding PID");
+                SysNum::GetPID | SysNum::GetTID => {
+                    info!("overriding {:?}", num);
+                    let pid = self.virtual_pid(result);
+                    tracee.set_result(pid as u64)?;
+                }
+                SysNum::GetPPID => {
+                    info!("overriding {:?}", num);
+                    // The parent of the first process is outside of the tree,
+                    // as with PID namespaces.
+                    let pid = self.outer_pid(result);
                     tracee.set_result(pid as u64)?;
                 }
-                _ => {
-                    warn!("cannot overwrite pid for syscall {:?}", num);
+                // User and group ids are left to the identity manager.
+                _ => {}
+            }
+        }
+
+        Ok(PIDRecord {
+            syscall,
+            pid: result as u32,
+        })
+    }
+
+    // Translate the PIDs returned by fork() and wait() to the parent.
+    pub fn result(&mut self, tracee: &mut Tracee, num: SysNum) -> Result<()> {
+        if self.base.is_none() {
+            return Ok(());
+        }
+        let result = tracee.registers().rax as i64;
+        match num {
+            SysNum::Clone | SysNum::Clone3 | SysNum::Fork | SysNum::VFork if result > 0 => {
+                let pid = self.virtual_pid(result as i32);
+                debug!(real = result, pid, "translating {:?} result", num);
+                tracee.set_result(pid as u64)?;
+            }
+            SysNum::Wait if result > 0 => {
+                let pid = self.waited_pid(result as i32, true);
+                debug!(real = result, pid, "translating {:?} result", num);
+                tracee.set_result(pid as u64)?;
+            }
+            SysNum::WaitId if result == 0 && tracee.registers().rdx != 0 => {
+                let addr = tracee.registers().rdx + SI_PID;
+                let data = tracee.read_memory(addr, 4)?;
+                let real = i32::from_ne_bytes(data[0..4].try_into().unwrap());
+                if real > 0 {
+                    let reaped = tracee.registers().r10 & WNOWAIT == 0;
+                    let pid = self.waited_pid(real, reaped);
+                    tracee.write_bytes(addr, &pid.to_ne_bytes())?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Translate the virtual PIDs passed to wait() and kill() to real ones.
+    pub fn translate_args(&self, tracee: &mut Tracee, num: SysNum) -> Result<()> {
+        if self.base.is_none() {
+            return Ok(());
+        }
+        let registers = tracee.registers();
+        let args: &[(u8, u64)] = match num {
+            // Negative PIDs are process groups, named after their leader.
+            SysNum::Wait | SysNum::Kill => &[(1, registers.rdi)],
+            SysNum::WaitId if matches!(registers.rdi, P_PID | P_PGID) => &[(2, registers.rsi)],
+            SysNum::TKill => &[(1, registers.rdi)],
+            SysNum::TGKill => &[(1, registers.rdi), (2, registers.rsi)],
+            _ => return Ok(()),
+        };
+        for &(index, value) in args {
+            let value = value as i32;
+            let real = match value {
+                // Special values: any child, the caller's group, all processes.
+                -1..=0 => continue,
+                pid if pid > 0 => self.real_pid(pid as u32).unwrap_or(pid),
+                group => -self.real_pid(-group as u32).unwrap_or(-group),
+            };
+            if real != value {
+                debug!(pid = value, real, "translating {:?} argument", num);
+                tracee.set_arg(index, real as u32 as u64)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Where a path under /proc really is, with virtual PIDs replaced by real
+    // ones. The status and stat files are copied with their PIDs made virtual.
+    pub fn proc_path(&mut self, tracee: &Tracee, path: &Path) -> Result<Option<String>> {
+        if self.base.is_none() {
+            return Ok(None);
+        }
+        let Ok(rest) = path.strip_prefix("/proc") else {
+            return Ok(None);
+        };
+        let mut components: Vec<String> = rest
+            .iter()
+            .map(|component| component.to_string_lossy().to_string())
+            .collect();
+        let mut translated = false;
+        match components.first().map(String::as_str) {
+            // The tracer has its own /proc/self.
+            Some("self") => components[0] = tracee.tgid()?.to_string(),
+            Some("thread-self") => {
+                let task = [
+                    tracee.tgid()?.to_string(),
+                    "task".to_string(),
+                    tracee.pid().to_string(),
+                ];
+                components.splice(0..1, task);
+            }
+            Some(pid) => match pid.parse().ok().and_then(|pid| self.real_pid(pid)) {
+                Some(real) => {
+                    components[0] = real.to_string();
+                    translated = true;
                 }
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        }
+        let task = components.len() > 2 && components[1] == "task";
+        if task {
+            if let Some(real) = components[2]
+                .parse()
+                .ok()
+                .and_then(|tid| self.real_pid(tid))
+            {
+                components[2] = real.to_string();
+                translated = true;
             }
         }
+        let real = PathBuf::from("/proc").join(components.join("/"));
+        let real_str = real.to_string_lossy().to_string();
+
+        let file = components.last().map(String::as_str);
+        if components.len() == 2 || (task && components.len() == 4) {
+            let content = match (file, fs::read_to_string(&real)) {
+                (Some("status"), Ok(content)) => self.status(&content),
+                (Some("stat"), Ok(content)) => self.stat(&content),
+                _ => return Ok(translated.then_some(real_str)),
+            };
+            let mut file = NamedTempFile::new()?;
+            file.write_all(content.as_bytes())?;
+            let dest = file.path().to_string_lossy().to_string();
+            self.proc_file = Some(file);
+            return Ok(Some(dest));
+        }
+        Ok(translated.then_some(real_str))
+    }
+
+    fn status(&mut self, content: &str) -> String {
+        let mut status = String::new();
+        for line in content.lines() {
+            let pid = line.split_once(':').and_then(|(key, value)| {
+                let real = value.split_whitespace().last()?.parse().ok()?;
+                let pid = match key {
+                    "Tgid" | "Pid" | "NStgid" | "NSpid" => self.virtual_pid(real),
+                    "PPid" => self.outer_pid(real),
+                    // The program is not traced, as far as it knows.
+                    "TracerPid" => 0,
+                    _ => return None,
+                };
+                Some((key, pid))
+            });
+            match pid {
+                Some((key, pid)) => status.push_str(&format!("{}:\t{}\n", key, pid)),
+                None => status.push_str(&format!("{}\n", line)),
+            }
+        }
+        status
+    }
+
+    fn stat(&mut self, content: &str) -> String {
+        // The command name may contain spaces and parentheses.
+        let Some(end) = content.rfind(')') else {
+            return content.to_string();
+        };
+        let (head, tail) = content.split_at(end + 1);
+        let Some((pid, comm)) = head.split_once(' ') else {
+            return content.to_string();
+        };
+        let pid = pid.parse().map(|pid| self.virtual_pid(pid).to_string());
+        let mut fields: Vec<String> = tail.split_whitespace().map(String::from).collect();
+        // Parent, process group and session.
+        for field in fields.iter_mut().skip(1).take(3) {
+            if let Ok(real) = field.parse() {
+                *field = self.outer_pid(real).to_string();
+            }
+        }
+        format!(
+            "{} {} {}\n",
+            pid.unwrap_or_default(),
+            comm,
+            fields.join(" ")
+        )
+    }
+
+    // Virtual PID of a process that may be outside of the tree.
+    fn outer_pid(&self, real: i32) -> u32 {
+        self.pids.get(&real).copied().unwrap_or(0)
+    }
+
+    // Virtual PID of a live process, given the next one if it is new. A
+    // real PID is only reused once its previous process is reaped.
+    fn virtual_pid(&mut self, real: i32) -> u32 {
+        if let Some(&pid) = self.pids.get(&real) {
+            return pid;
+        }
+        // Reaped outside of the tree.
+        if let Some(old) = self.exited.remove(&real) {
+            self.reals.remove(&old);
+        }
+        let pid = self.next;
+        self.next += 1;
+        self.pids.insert(real, pid);
+        self.reals.insert(pid, real);
+        pid
+    }
+
+    // Virtual PID of a child returned by wait(), which forgets it once reaped.
+    fn waited_pid(&mut self, real: i32, reaped: bool) -> u32 {
+        let Some(&pid) = self.exited.get(&real) else {
+            // Stopped or continued.
+            return self.virtual_pid(real);
+        };
+        if reaped {
+            self.exited.remove(&real);
+            self.reals.remove(&pid);
+        }
+        pid
+    }
 
-        Ok(PIDRecord { pid: result as u32 })
+    fn real_pid(&self, pid: u32) -> Option<i32> {
+        self.reals.get(&pid).copied()
     }
 }
@@ -1,41 +1,89 @@
-use std::io::Result;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Result,
+    path::Path,
+};
 
 use rand::{rngs::StdRng, RngCore, SeedableRng};
-use tracing::info;
+use rand_chacha::ChaCha20Rng;
+use tracing::{info, warn};
 
 use crate::{
-    recorder::RandomRecord,
+    config::{BytesEncoding, RandomAlgorithm, RandomConfig, RecordConfig},
+    recorder::{RandomData, RandomRecord},
     tracer::{OperationResult, Tracee},
 };
 
-const SEED: u64 = 0xdeadbeef;
+enum Generator {
+    Std(StdRng),
+    ChaCha20(ChaCha20Rng),
+    Constant(u8),
+}
+
+// Devices whose reads are served like getrandom().
+const RANDOM_DEVICES: [&str; 2] = ["/dev/urandom", "/dev/random"];
 
 pub struct RandomManager {
-    rng: StdRng,
+    rng: Generator,
     active: bool,
+    record: bool,
+    encoding: Option<BytesEncoding>,
+    replay: Option<VecDeque<RandomRecord>>,
+    // Whether the fds read by each thread are random devices. Entries are
+    // dropped when the fd may be closed, so that its number can be reused.
+    devices: HashMap<(i32, u64), bool>,
 }
 
 impl RandomManager {
-    pub fn new(active: bool) -> Self {
+    pub fn new(cfg: &RandomConfig, record: &RecordConfig) -> Self {
+        RandomManager {
+            active: cfg.enabled,
+            record: record.random,
+            rng: Generator::new(cfg),
+            encoding: record.random_bytes,
+            replay: None,
+            devices: HashMap::new(),
+        }
+    }
+
+    // Records without bytes are replayed with the seeded generator, using the
+    // recorded lengths.
+    pub fn replay(
+        records: VecDeque<RandomRecord>,
+        cfg: &RandomConfig,
+        record: &RecordConfig,
+    ) -> Self {
         RandomManager {
-            active,
-            rng: StdRng::seed_from_u64(SEED),
+            active: true,
+            record: record.random,
+            rng: Generator::new(cfg),
+            encoding: record.random_bytes,
+            replay: Some(records),
+            devices: HashMap::new(),
         }
     }
 
     pub fn getrandom(&mut self, len: usize) -> Vec<u8> {
         let mut buf = vec![0; len];
-        self.rng.fill_bytes(&mut buf);
+        match self.rng {
+            Generator::Std(ref mut rng) => rng.fill_bytes(&mut buf),
+            Generator::ChaCha20(ref mut rng) => rng.fill_bytes(&mut buf),
+            Generator::Constant(byte) => buf.fill(byte),
+        }
         buf
     }
 
     pub fn process(&mut self, tracee: &mut Tracee, len: usize, addr: u64) -> Result<RandomRecord> {
         let result = tracee.get_result()?;
+        let mut written = None;
         if self.active {
-            // Overwrite result with 0s.
-            let data = self.getrandom(len);
+            let data = match self.next_replayed(len)? {
+                Some(data) => data,
+                None => self.getrandom(len),
+            };
             tracee.write_bytes(addr, &data)?;
-            tracee.set_result(len as u64)?;
+            tracee.set_result(data.len() as u64)?;
+            written = Some(data);
         }
         match result {
             OperationResult::Success(num_bytes) => {
@@ -45,6 +93,93 @@ impl RandomManager {
                 info!("getrandom({})", errno);
             }
         }
-        Ok(RandomRecord { length: len })
+
+        let data = match (self.encoding, written, result) {
+            (None, _, _) => None,
+            (Some(encoding), Some(data), _) => Some(RandomData::encode(&data, encoding)),
+            (Some(encoding), None, OperationResult::Success(num_bytes)) => {
+                let data = tracee.read_memory(addr, num_bytes as usize)?;
+                Some(RandomData::encode(&data, encoding))
+            }
+            (Some(_), None, OperationResult::Error(_)) => None,
+        };
+        Ok(RandomRecord { length: len, data })
+    }
+
+    // Reads from random devices are handled like getrandom(), other reads are
+    // left untouched.
+    pub fn read(
+        &mut self,
+        tracee: &mut Tracee,
+        fd: u64,
+        addr: u64,
+    ) -> Result<Option<RandomRecord>> {
+        if !self.active && !self.record {
+            return Ok(None);
+        }
+        let key = (tracee.pid(), fd);
+        let device = match self.devices.get(&key) {
+            Some(&device) => device,
+            None => {
+                let Ok(path) = tracee.fd_path(fd) else {
+                    return Ok(None);
+                };
+                let device = RANDOM_DEVICES
+                    .iter()
+                    .any(|device| path == Path::new(device));
+                self.devices.insert(key, device);
+                device
+            }
+        };
+        if !device {
+            return Ok(None);
+        }
+        info!("read({}) from a random device", fd);
+        // The count may have been shortened by a fault rule.
+        let len = tracee.registers().rdx as usize;
+        self.process(tracee, len, addr).map(Some)
+    }
+
+    // Forget an fd closed by any thread, or all of them.
+    pub fn close(&mut self, fd: Option<u64>) {
+        match fd {
+            Some(fd) => self.devices.retain(|&(_, other), _| other != fd),
+            None => self.devices.clear(),
+        }
+    }
+
+    // Forget the fds of a thread that is gone, as its id can be reused.
+    pub fn exit(&mut self, pid: i32) {
+        self.devices.retain(|&(other, _), _| other != pid);
+    }
+
+    fn next_replayed(&mut self, len: usize) -> Result<Option<Vec<u8>>> {
+        let Some(records) = self.replay.as_mut() else {
+            return Ok(None);
+        };
+        let Some(record) = records.pop_front() else {
+            warn!("getrandom() is not in the recording");
+            return Ok(None);
+        };
+        // Never write past the buffer given by the tracee.
+        let data = match record.data {
+            Some(data) => {
+                let mut data = data.decode()?;
+                data.truncate(len);
+                data
+            }
+            None => self.getrandom(record.length.min(len)),
+        };
+        Ok(Some(data))
+    }
+}
+
+impl Generator {
+    fn new(cfg: &RandomConfig) -> Self {
+        match cfg.algorithm {
+            RandomAlgorithm::Std => Generator::Std(StdRng::seed_from_u64(cfg.seed)),
+            RandomAlgorithm::ChaCha20 => Generator::ChaCha20(ChaCha20Rng::seed_from_u64(cfg.seed)),
+            RandomAlgorithm::Constant => Generator::Constant(cfg.fill),
+        }
     }
 }
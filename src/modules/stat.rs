@@ -0,0 +1,130 @@
+use std::{
+    io::{Error, Result},
+    path::Path,
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+use super::file::resolve;
+use crate::{
+    config::TimestampConfig,
+    tracer::{OperationResult, Tracee},
+    SysNum,
+};
+
+// Offsets of the access, modification and status change times in struct stat,
+// and of the access, birth, status change and modification times in struct
+// statx.
+const STAT_TIMES: [u64; 3] = [72, 88, 104];
+const STATX_TIMES: [u64; 4] = [0x40, 0x50, 0x60, 0x70];
+
+pub struct StatManager {
+    config: Option<TimestampConfig>,
+    follow_symlinks: bool,
+}
+
+impl StatManager {
+    // Timestamps come from the epoch, or else from the virtual realtime clock,
+    // so one of them must be set.
+    pub fn new(
+        config: Option<TimestampConfig>,
+        follow_symlinks: bool,
+        virtual_clock: bool,
+    ) -> Result<Self> {
+        if config
+            .as_ref()
+            .is_some_and(|config| config.epoch.is_none() && !virtual_clock)
+        {
+            return Err(Error::other(
+                "redirect.timestamps needs an epoch when redirect.time is unset",
+            ));
+        }
+        Ok(StatManager {
+            config,
+            follow_symlinks,
+        })
+    }
+
+    pub fn lookup(
+        &self,
+        tracee: &mut Tracee,
+        path: &Path,
+        num: SysNum,
+        now: Option<Duration>,
+    ) -> Result<()> {
+        if self.config.is_none() || !is_stat(num) {
+            return Ok(());
+        }
+        let path = resolve(tracee, path, self.follow_symlinks);
+        self.rewrite(tracee, &path, num, now)
+    }
+
+    pub fn fstat(
+        &self,
+        tracee: &mut Tracee,
+        fd: u64,
+        num: SysNum,
+        now: Option<Duration>,
+    ) -> Result<()> {
+        if self.config.is_none() {
+            return Ok(());
+        }
+        let path = match tracee.fd_path(fd) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("failed to find the file of fd {}: {}", fd, e);
+                return Ok(());
+            }
+        };
+        self.rewrite(tracee, &path, num, now)
+    }
+
+    // Overwrite the timestamps returned by a successful stat.
+    fn rewrite(
+        &self,
+        tracee: &mut Tracee,
+        path: &Path,
+        num: SysNum,
+        now: Option<Duration>,
+    ) -> Result<()> {
+        let Some(ref config) = self.config else {
+            return Ok(());
+        };
+        let path_str = path.to_string_lossy();
+        if !config.paths.is_empty() && !config.paths.iter().any(|p| p.matches(&path_str)) {
+            return Ok(());
+        }
+        // The clock is only missing when replaying.
+        let Some(time) = config.epoch.map(Duration::from_secs).or(now) else {
+            return Ok(());
+        };
+        if let OperationResult::Error(_) = tracee.get_result()? {
+            return Ok(());
+        }
+
+        let registers = tracee.registers();
+        let (addr, offsets) = match num {
+            SysNum::Stat | SysNum::LStat | SysNum::FStat => (registers.rsi, &STAT_TIMES[..]),
+            SysNum::NewFstatAt => (registers.rdx, &STAT_TIMES[..]),
+            SysNum::StatX => (registers.r8, &STATX_TIMES[..]),
+            _ => unreachable!("unexpected stat syscall {:?}", num),
+        };
+        // Both layouts have 64-bit seconds followed by the nanoseconds, which
+        // are 32 bits in struct statx_timestamp.
+        let mut timestamp = Vec::from(time.as_secs().to_ne_bytes());
+        timestamp.extend_from_slice(&time.subsec_nanos().to_ne_bytes());
+        info!("rewriting timestamps of {} to {:?}", path_str, time);
+        for offset in offsets {
+            tracee.write_bytes(addr + offset, &timestamp)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_stat(num: SysNum) -> bool {
+    matches!(
+        num,
+        SysNum::Stat | SysNum::LStat | SysNum::NewFstatAt | SysNum::StatX
+    )
+}
@@ -1,35 +1,66 @@
 use std::{
+    collections::{HashMap, VecDeque},
     io::Result,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
+use nix::errno::Errno;
 use tracing::{info, warn};
 
 use crate::{
+    config::{CpuTimePolicy, TimeConfig, TimeMode},
     recorder::TimeRecord,
     syscall::Clock,
     tracer::{OperationResult, Tracee},
     SysNum,
 };
 
+// Offsets of the status and time fields in struct timex.
+const TIMEX_STATUS: u64 = 40;
+const TIMEX_TIME: u64 = 72;
+const STA_NANO: i32 = 0x2000;
+// Latest time a virtual clock can show, the limit of 64 bits nanosecond
+// timestamps, in 2262. Sleeps such as `sleep infinity` stop the clocks there.
+const MAX_TIME: Duration = Duration::from_nanos(i64::MAX as u64);
+
 pub struct TimeManager {
-    time: Option<SystemTime>,
-    // real_ref: SystemTime,
-    // mono_ref: Instant,
+    config: Option<TimeConfig>,
+    // Host time at which the virtual clocks start.
+    start: Instant,
+    // Number of reads of the virtual clocks, with the step mode.
+    steps: u64,
+    // Total duration of virtual sleeps.
+    slept: Duration,
+    // CPU time of each thread and process, with the step policy.
+    cpu_times: HashMap<(bool, i32), Duration>,
+    replay: Option<VecDeque<TimeRecord>>,
 }
 
 impl TimeManager {
-    pub fn new(timestamp: Option<u64>) -> Self {
-        let time = timestamp.map(|secs| (SystemTime::UNIX_EPOCH + Duration::from_secs(secs)));
+    pub fn new(config: Option<TimeConfig>) -> Self {
+        TimeManager {
+            config,
+            start: Instant::now(),
+            steps: 0,
+            slept: Duration::ZERO,
+            cpu_times: HashMap::new(),
+            replay: None,
+        }
+    }
+
+    pub fn replay(records: VecDeque<TimeRecord>) -> Self {
         TimeManager {
-            time,
-            // real_ref: SystemTime::now(),
-            // mono_ref: Instant::now(),
+            config: None,
+            start: Instant::now(),
+            steps: 0,
+            slept: Duration::ZERO,
+            cpu_times: HashMap::new(),
+            replay: Some(records),
         }
     }
 
     pub fn process(
-        &self,
+        &mut self,
         tracee: &mut Tracee,
         num: SysNum,
         clock: Clock,
@@ -39,7 +70,7 @@ impl TimeManager {
             OperationResult::Success(_) => {
                 let result = self.get_result(tracee, num, addr)?;
                 info!("time({:?}, {:?})", clock, result);
-                Some(result)
+                result
             }
             OperationResult::Error(errno) => {
                 info!("time({:?}, {})", clock, errno);
@@ -47,38 +78,15 @@ impl TimeManager {
             }
         };
 
-        if let (Some(time), Some(true_time)) = (self.time, true_time) {
-            info!("overriding time");
-            let new_time = match clock {
-                Clock::Realtime(_) => time,
-                _ => true_time,
-            };
-            match num {
-                SysNum::ClockGetTime => {
-                    let secs = new_time
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
-                    let nanos = new_time
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .subsec_nanos();
-                    let mut bytes = Vec::from(secs.to_ne_bytes());
-                    bytes.append(&mut Vec::from(nanos.to_ne_bytes()));
-                    tracee.write_bytes(addr, &bytes)?;
-                }
-                SysNum::Time => {
-                    if addr != 0 {
-                        warn!("time syscall with non-null addr, which is not supported.");
-                    }
-                    let secs = new_time
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
-                    tracee.set_result(secs)?;
-                }
-                _ => unreachable!("unexpected time syscall {:?}", num),
-            };
+        let replayed = self.next_replayed(clock);
+        if true_time.is_some() {
+            if let Some(replayed) = replayed {
+                info!("replaying time");
+                self.set_time(tracee, num, addr, replayed)?;
+            } else if let Some(new_time) = self.virtual_time(tracee, clock)? {
+                info!("overriding time");
+                self.set_time(tracee, num, addr, new_time)?;
+            }
         }
 
         Ok(TimeRecord {
@@ -87,22 +95,262 @@ impl TimeManager {
         })
     }
 
-    fn get_result(&self, tracee: &mut Tracee, num: SysNum, addr: u64) -> Result<SystemTime> {
+    // Virtual clocks tick every nanosecond, whatever the host resolution.
+    pub fn resolution(&self, tracee: &mut Tracee, clock: Clock, addr: u64) -> Result<()> {
+        match tracee.get_result()? {
+            OperationResult::Success(_) => info!("clock_getres({:?})", clock),
+            OperationResult::Error(errno) => {
+                info!("clock_getres({:?}): {}", clock, errno);
+                return Ok(());
+            }
+        }
+        if addr != 0 && self.is_virtual(clock) {
+            let mut bytes = Vec::from(0u64.to_ne_bytes());
+            bytes.extend_from_slice(&1u64.to_ne_bytes());
+            tracee.write_bytes(addr, &bytes)?;
+        }
+        Ok(())
+    }
+
+    fn is_virtual(&self, clock: Clock) -> bool {
+        let Some(ref config) = self.config else {
+            return false;
+        };
+        match clock {
+            Clock::Realtime(_) => true,
+            Clock::Monotonic(_) => config.monotonic.is_some(),
+            Clock::ProcessCPUTime | Clock::ThreadCPUTime => {
+                !matches!(config.cpu, CpuTimePolicy::Real)
+            }
+            Clock::Other(_) => false,
+        }
+    }
+
+    // With virtual sleeps, timeouts expire at once and move the virtual clocks
+    // forward instead.
+    pub fn sleep(
+        &mut self,
+        tracee: &mut Tracee,
+        num: SysNum,
+        clock: Clock,
+        timeout: Duration,
+        absolute: bool,
+    ) -> Result<()> {
+        if !self.config.as_ref().is_some_and(|config| config.sleep) {
+            return Ok(());
+        }
+        let duration = if absolute {
+            // Deadlines on host clocks can't be skipped.
+            let Some(now) = self.now(clock) else {
+                return Ok(());
+            };
+            timeout.saturating_sub(now)
+        } else {
+            timeout
+        };
+
+        let expired = match num {
+            SysNum::Nanosleep | SysNum::ClockNanosleep => {
+                tracee.skip_syscall(0)?;
+                true
+            }
+            SysNum::Poll | SysNum::EpollWait | SysNum::EpollPWait => {
+                let arg = if num == SysNum::Poll { 3 } else { 4 };
+                tracee.set_arg(arg, 0)?;
+                matches!(tracee.get_result()?, OperationResult::Success(0))
+            }
+            SysNum::PPoll
+            | SysNum::EpollPWait2
+            | SysNum::Select
+            | SysNum::PSelect6
+            | SysNum::Futex => {
+                let arg = match num {
+                    SysNum::PPoll => 3,
+                    SysNum::Select | SysNum::PSelect6 => 5,
+                    _ => 4,
+                };
+                // A zero timespec or timeval, which is also a deadline in the
+                // past.
+                let zero = tracee.write_data(&[0; 16])?;
+                tracee.set_arg(arg, zero)?;
+                match tracee.get_result()? {
+                    OperationResult::Success(0) => num != SysNum::Futex,
+                    OperationResult::Error(Errno::ETIMEDOUT) => num == SysNum::Futex,
+                    _ => false,
+                }
+            }
+            _ => unreachable!("unexpected sleep syscall {:?}", num),
+        };
+        if expired {
+            info!("sleeping {:?} in {:?}", duration, num);
+            self.slept = self.slept.saturating_add(duration);
+        }
+        Ok(())
+    }
+
+    // Value of a virtual clock, or None if the clock is not virtualized.
+    // Times are represented as durations since the epoch, whatever the clock.
+    fn virtual_time(&mut self, tracee: &Tracee, clock: Clock) -> Result<Option<SystemTime>> {
+        let Some(ref config) = self.config else {
+            return Ok(None);
+        };
+        let time = match clock {
+            Clock::Realtime(_) | Clock::Monotonic(_) => {
+                let time = self.now(clock);
+                // Each read moves the clocks forward, with the step mode.
+                if time.is_some() {
+                    self.steps = self.steps.saturating_add(1);
+                }
+                time
+            }
+            Clock::ProcessCPUTime | Clock::ThreadCPUTime => match config.cpu {
+                CpuTimePolicy::Real => None,
+                CpuTimePolicy::Frozen => Some(Duration::ZERO),
+                CpuTimePolicy::Step => {
+                    let key = match clock {
+                        Clock::ThreadCPUTime => (true, tracee.pid()),
+                        _ => (false, tracee.tgid()?),
+                    };
+                    let step = Duration::from_nanos(config.cpu_step);
+                    let time = self.cpu_times.entry(key).or_default();
+                    *time += step;
+                    Some(*time)
+                }
+            },
+            Clock::Other(_) => None,
+        };
+        Ok(time.map(|time| SystemTime::UNIX_EPOCH + time))
+    }
+
+    // Current value of the virtual realtime clock, since the epoch.
+    pub fn realtime(&self) -> Option<Duration> {
+        self.now(Clock::Realtime(0))
+    }
+
+    // Current value of a virtual realtime or monotonic clock, without reading
+    // it.
+    fn now(&self, clock: Clock) -> Option<Duration> {
+        let config = self.config.as_ref()?;
+        let start = match clock {
+            Clock::Realtime(_) => config.realtime,
+            Clock::Monotonic(_) => config.monotonic?,
+            _ => return None,
+        };
+        let elapsed = match config.mode {
+            TimeMode::Frozen => Duration::ZERO,
+            TimeMode::Offset => self.start.elapsed(),
+            // Too far in the future is clamped below.
+            TimeMode::Scaled => {
+                Duration::try_from_secs_f64(self.start.elapsed().as_secs_f64() * config.scale)
+                    .unwrap_or(MAX_TIME)
+            }
+            // The first read sees the start time.
+            TimeMode::Step => Duration::from_nanos(config.step.saturating_mul(self.steps)),
+        };
+        let time = Duration::from_secs(start)
+            .saturating_add(elapsed)
+            .saturating_add(self.slept);
+        Some(time.min(MAX_TIME))
+    }
+
+    fn next_replayed(&mut self, clock: Clock) -> Option<SystemTime> {
+        let records = self.replay.as_mut()?;
+        let Some(record) = records.pop_front() else {
+            warn!("time({:?}) is not in the recording", clock);
+            return None;
+        };
+        if std::mem::discriminant(&record.clock) != std::mem::discriminant(&clock) {
+            warn!(
+                "time({:?}) was recorded as time({:?}), replaying anyway",
+                clock, record.clock
+            );
+        }
+        record.time
+    }
+
+    fn set_time(
+        &self,
+        tracee: &mut Tracee,
+        num: SysNum,
+        addr: u64,
+        new_time: SystemTime,
+    ) -> Result<()> {
+        let time = new_time.duration_since(SystemTime::UNIX_EPOCH).unwrap();
         match num {
+            SysNum::ClockGetTime => {
+                let mut bytes = Vec::from(time.as_secs().to_ne_bytes());
+                bytes.extend_from_slice(&time.subsec_nanos().to_ne_bytes());
+                tracee.write_bytes(addr, &bytes)?;
+            }
+            SysNum::Time => {
+                tracee.set_result(time.as_secs())?;
+                if addr != 0 {
+                    tracee.write_bytes(addr, &time.as_secs().to_ne_bytes())?;
+                }
+            }
+            SysNum::GetTimeOfDay => {
+                let mut bytes = Vec::from(time.as_secs().to_ne_bytes());
+                bytes.extend_from_slice(&(time.subsec_micros() as u64).to_ne_bytes());
+                tracee.write_bytes(addr, &bytes)?;
+            }
+            SysNum::AdjTimex | SysNum::ClockAdjTime => {
+                let subsec = if self.timex_nano(tracee, addr)? {
+                    time.subsec_nanos()
+                } else {
+                    time.subsec_micros()
+                };
+                let mut bytes = Vec::from(time.as_secs().to_ne_bytes());
+                bytes.extend_from_slice(&(subsec as u64).to_ne_bytes());
+                tracee.write_bytes(addr + TIMEX_TIME, &bytes)?;
+            }
+            _ => unreachable!("unexpected time syscall {:?}", num),
+        };
+        Ok(())
+    }
+
+    // Time returned by the syscall, if any.
+    fn get_result(
+        &self,
+        tracee: &mut Tracee,
+        num: SysNum,
+        addr: u64,
+    ) -> Result<Option<SystemTime>> {
+        let time = match num {
             SysNum::ClockGetTime => {
                 let data = tracee.read_memory(addr, 12)?;
                 let secs = u64::from_ne_bytes(data[0..8].try_into().unwrap());
                 let nanos = u32::from_ne_bytes(data[8..12].try_into().unwrap());
-                let time = SystemTime::UNIX_EPOCH
-                    + Duration::from_nanos(secs * 1_000_000_000 + nanos as u64);
-                Ok(time)
+                Duration::new(secs, nanos)
             }
-            SysNum::Time => {
-                let secs = tracee.registers().rax;
-                let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
-                Ok(time)
+            SysNum::Time => Duration::from_secs(tracee.registers().rax),
+            // Only the time zone may be requested.
+            SysNum::GetTimeOfDay if addr == 0 => return Ok(None),
+            SysNum::GetTimeOfDay => {
+                let data = tracee.read_memory(addr, 16)?;
+                let secs = u64::from_ne_bytes(data[0..8].try_into().unwrap());
+                let micros = u64::from_ne_bytes(data[8..16].try_into().unwrap());
+                Duration::from_secs(secs) + Duration::from_micros(micros)
+            }
+            SysNum::AdjTimex | SysNum::ClockAdjTime => {
+                let data = tracee.read_memory(addr + TIMEX_TIME, 16)?;
+                let secs = u64::from_ne_bytes(data[0..8].try_into().unwrap());
+                let subsec = u64::from_ne_bytes(data[8..16].try_into().unwrap());
+                if self.timex_nano(tracee, addr)? {
+                    Duration::from_secs(secs) + Duration::from_nanos(subsec)
+                } else {
+                    Duration::from_secs(secs) + Duration::from_micros(subsec)
+                }
             }
             _ => unreachable!("unexpected time syscall {:?}", num),
-        }
+        };
+        Ok(Some(SystemTime::UNIX_EPOCH + time))
+    }
+
+    // Whether the time in a struct timex has nanoseconds instead of
+    // microseconds.
+    fn timex_nano(&self, tracee: &Tracee, addr: u64) -> Result<bool> {
+        let data = tracee.read_memory(addr + TIMEX_STATUS, 4)?;
+        let status = i32::from_ne_bytes(data[0..4].try_into().unwrap());
+        Ok(status & STA_NANO != 0)
     }
 }
@@ -0,0 +1,135 @@
+use std::{
+    collections::VecDeque,
+    io::{Result, Write},
+};
+
+use tempfile::NamedTempFile;
+use tracing::{info, warn};
+
+use crate::{
+    config::UnameConfig,
+    recorder::UnameRecord,
+    tracer::{OperationResult, Tracee},
+};
+
+// Size of each field of struct utsname, with its null byte.
+const FIELD_LENGTH: usize = 65;
+
+pub struct UnameManager {
+    config: Option<UnameConfig>,
+    // Files of /proc/sys/kernel and /etc/hostname with the configured names,
+    // removed when the manager is dropped.
+    files: Vec<(&'static str, NamedTempFile)>,
+    replay: Option<VecDeque<UnameRecord>>,
+}
+
+impl UnameManager {
+    pub fn new(config: Option<UnameConfig>) -> Result<Self> {
+        let mut files = Vec::new();
+        if let Some(ref config) = config {
+            let names = [
+                ("/proc/sys/kernel/ostype", &config.sysname),
+                ("/proc/sys/kernel/hostname", &config.nodename),
+                ("/etc/hostname", &config.nodename),
+                ("/proc/sys/kernel/osrelease", &config.release),
+                ("/proc/sys/kernel/version", &config.version),
+                ("/proc/sys/kernel/domainname", &config.domainname),
+            ];
+            for (path, name) in names {
+                if let Some(name) = name {
+                    let mut file = NamedTempFile::new()?;
+                    writeln!(file, "{}", name)?;
+                    file.flush()?;
+                    files.push((path, file));
+                }
+            }
+        }
+        Ok(UnameManager {
+            config,
+            files,
+            replay: None,
+        })
+    }
+
+    // Names are replayed, except for those set in the configuration.
+    pub fn replay(config: Option<UnameConfig>, records: VecDeque<UnameRecord>) -> Result<Self> {
+        Ok(UnameManager {
+            replay: Some(records),
+            ..UnameManager::new(config)?
+        })
+    }
+
+    // Files to redirect so that they agree with uname().
+    pub fn redirects(&self) -> Vec<(String, String)> {
+        self.files
+            .iter()
+            .map(|(from, file)| (from.to_string(), file.path().to_string_lossy().to_string()))
+            .collect()
+    }
+
+    pub fn process(&mut self, tracee: &mut Tracee, addr: u64) -> Result<Option<UnameRecord>> {
+        if let OperationResult::Error(errno) = tracee.get_result()? {
+            info!("uname(): {}", errno);
+            return Ok(None);
+        }
+        let data = tracee.read_memory(addr, FIELD_LENGTH * 6)?;
+        let field = |index: usize| {
+            let field = &data[index * FIELD_LENGTH..(index + 1) * FIELD_LENGTH];
+            let end = field.iter().position(|&c| c == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).to_string()
+        };
+        let record = UnameRecord {
+            sysname: field(0),
+            nodename: field(1),
+            release: field(2),
+            version: field(3),
+            machine: field(4),
+            domainname: field(5),
+        };
+        info!("uname({:?})", record);
+
+        let mut names = record.clone();
+        if let Some(ref mut records) = self.replay {
+            match records.pop_front() {
+                Some(replayed) => {
+                    info!("replaying uname");
+                    names = replayed;
+                }
+                None => warn!("uname() is not in the recording"),
+            }
+        }
+        if let Some(ref config) = self.config {
+            let fields = [
+                (&mut names.sysname, &config.sysname),
+                (&mut names.nodename, &config.nodename),
+                (&mut names.release, &config.release),
+                (&mut names.version, &config.version),
+                (&mut names.machine, &config.machine),
+                (&mut names.domainname, &config.domainname),
+            ];
+            for (name, value) in fields {
+                if let Some(value) = value {
+                    name.clone_from(value);
+                }
+            }
+        }
+
+        let mut data = Vec::with_capacity(FIELD_LENGTH * 6);
+        for name in [
+            &names.sysname,
+            &names.nodename,
+            &names.release,
+            &names.version,
+            &names.machine,
+            &names.domainname,
+        ] {
+            // Longer names are truncated to keep their null byte.
+            let mut field = [0; FIELD_LENGTH];
+            let len = name.len().min(FIELD_LENGTH - 1);
+            field[..len].copy_from_slice(&name.as_bytes()[..len]);
+            data.extend_from_slice(&field);
+        }
+        tracee.write_bytes(addr, &data)?;
+        Ok(Some(record))
+    }
+}
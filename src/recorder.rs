@@ -1,9 +1,10 @@
 use std::fs::OpenOptions;
-use std::io::{BufWriter, Result, stdout, Write};
+use std::io::{BufWriter, Error, ErrorKind, Result, stdout, Write};
 
-use serde::Serialize;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
 
-use crate::config::RecordConfig;
+use crate::config::{BytesEncoding, RecordConfig};
 use crate::syscall::Clock;
 
 pub struct Recorder {
@@ -11,7 +12,7 @@ pub struct Recorder {
     config: RecordConfig,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FileRecord {
     pub path: String,
     pub success: bool,
@@ -19,29 +20,119 @@ pub struct FileRecord {
     pub write: bool,
 }
 
-#[derive(Debug, Serialize)]
+// Syscalls looking a path up without opening it, such as stat() or access().
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PathRecord {
+    pub syscall: String,
+    pub path: String,
+    pub success: bool,
+}
+
+// Syscalls modifying the file system, such as unlink() or rename().
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    pub syscall: String,
+    pub paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub success: bool,
+}
+
+// Syscalls skipped because of a deny rule.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DenyRecord {
+    pub syscall: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub errno: String,
+}
+
+// Faults injected in a syscall, either an error or a shortened count.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FaultRecord {
+    pub syscall: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+}
+
+// Socket creation, and syscalls with a socket address such as connect().
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NetRecord {
+    pub syscall: String,
+    // Address family and socket type, for socket().
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    // Where the address was redirected to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno: Option<String>,
+    // Skipped by the offline mode.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub blocked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct RandomRecord {
     pub length: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<RandomData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RandomData {
+    Hex(String),
+    Base64(String),
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TimeRecord {
     pub clock: Clock,
     pub time: Option<std::time::SystemTime>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct PIDRecord {
+    // Empty in recordings made before it was added.
+    #[serde(default)]
+    pub syscall: String,
     pub pid: u32,
 }
 
-#[derive(Debug, Serialize)]
+// Fields of struct utsname, as returned by the kernel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnameRecord {
+    pub sysname: String,
+    pub nodename: String,
+    pub release: String,
+    pub version: String,
+    pub machine: String,
+    pub domainname: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Record {
     File(FileRecord),
+    Path(PathRecord),
+    Change(ChangeRecord),
+    Deny(DenyRecord),
+    Fault(FaultRecord),
+    Net(NetRecord),
     Random(RandomRecord),
     Time(TimeRecord),
     PID(PIDRecord),
+    Uname(UnameRecord),
 }
 
 impl Recorder {
@@ -68,6 +159,28 @@ impl Recorder {
                     return Ok(());
                 }
             }
+            Record::Path(_) | Record::Change(_) => {
+                if !self.config.files {
+                    return Ok(());
+                }
+            }
+            Record::Deny(_) => {
+                if !self.config.denied {
+                    return Ok(());
+                }
+            }
+            Record::Fault(_) => {
+                if !self.config.faults {
+                    return Ok(());
+                }
+            }
+            // Blocked attempts are always kept, as evidence of the offline
+            // mode.
+            Record::Net(ref record) => {
+                if !self.config.network && !record.blocked {
+                    return Ok(());
+                }
+            }
             Record::Random(_) => {
                 if !self.config.random {
                     return Ok(());
@@ -83,6 +196,11 @@ impl Recorder {
                     return Ok(());
                 }
             }
+            Record::Uname(_) => {
+                if !self.config.uname {
+                    return Ok(());
+                }
+            }
         };
         serde_json::to_writer(&mut self.output, &record)?;
         self.output.write_all(b"\n")?;
@@ -90,6 +208,37 @@ impl Recorder {
     }
 }
 
+impl RandomData {
+    pub fn encode(bytes: &[u8], encoding: BytesEncoding) -> Self {
+        match encoding {
+            BytesEncoding::Hex => {
+                RandomData::Hex(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+            }
+            BytesEncoding::Base64 => RandomData::Base64(STANDARD.encode(bytes)),
+        }
+    }
+
+    pub fn decode(&self) -> Result<Vec<u8>> {
+        match self {
+            RandomData::Hex(hex) => {
+                if hex.len() % 2 != 0 || !hex.is_ascii() {
+                    return Err(Error::new(ErrorKind::InvalidData, "invalid hex data"));
+                }
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| {
+                        u8::from_str_radix(&hex[i..i + 2], 16)
+                            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+                    })
+                    .collect()
+            }
+            RandomData::Base64(data) => STANDARD
+                .decode(data)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
 impl Drop for Recorder {
     fn drop(&mut self) {
         self.output.flush().unwrap();
@@ -102,6 +251,36 @@ impl From<FileRecord> for Record {
     }
 }
 
+impl From<PathRecord> for Record {
+    fn from(record: PathRecord) -> Self {
+        Record::Path(record)
+    }
+}
+
+impl From<ChangeRecord> for Record {
+    fn from(record: ChangeRecord) -> Self {
+        Record::Change(record)
+    }
+}
+
+impl From<DenyRecord> for Record {
+    fn from(record: DenyRecord) -> Self {
+        Record::Deny(record)
+    }
+}
+
+impl From<FaultRecord> for Record {
+    fn from(record: FaultRecord) -> Self {
+        Record::Fault(record)
+    }
+}
+
+impl From<NetRecord> for Record {
+    fn from(record: NetRecord) -> Self {
+        Record::Net(record)
+    }
+}
+
 impl From<RandomRecord> for Record {
     fn from(record: RandomRecord) -> Self {
         Record::Random(record)
@@ -118,4 +297,10 @@ impl From<PIDRecord> for Record {
     fn from(record: PIDRecord) -> Self {
         Record::PID(record)
     }
-}
\ No newline at end of file
+}
+
+impl From<UnameRecord> for Record {
+    fn from(record: UnameRecord) -> Self {
+        Record::Uname(record)
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufRead, BufReader, Error, ErrorKind, Result},
+    path::Path,
+};
+
+use crate::recorder::{FileRecord, PIDRecord, RandomRecord, Record, TimeRecord, UnameRecord};
+
+// Records of a previous run, grouped so that each manager can consume its own
+// share in the order they were produced.
+#[derive(Debug, Default)]
+pub struct Recording {
+    // File records are indexed by path, since the order of opens across
+    // processes is not stable from one run to the next.
+    pub(crate) files: HashMap<String, VecDeque<FileRecord>>,
+    pub(crate) random: VecDeque<RandomRecord>,
+    pub(crate) time: VecDeque<TimeRecord>,
+    pub(crate) pid: VecDeque<PIDRecord>,
+    pub(crate) uname: VecDeque<UnameRecord>,
+}
+
+impl Recording {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut recording = Recording::default();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Record = serde_json::from_str(&line).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid record on line {}: {}", index + 1, e),
+                )
+            })?;
+            recording.push(record);
+        }
+        Ok(recording)
+    }
+
+    fn push(&mut self, record: Record) {
+        match record {
+            Record::File(record) => self
+                .files
+                .entry(record.path.clone())
+                .or_default()
+                .push_back(record),
+            // Lookups and changes are not replayed, the file system handles
+            // them again, and neither is the network. Denials and faults come
+            // from the configuration.
+            Record::Path(_)
+            | Record::Change(_)
+            | Record::Net(_)
+            | Record::Deny(_)
+            | Record::Fault(_) => {}
+            Record::Random(record) => self.random.push_back(record),
+            Record::Time(record) => self.time.push_back(record),
+            Record::PID(record) => self.pid.push_back(record),
+            Record::Uname(record) => self.uname.push_back(record),
+        }
+    }
+}
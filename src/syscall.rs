@@ -3,14 +3,14 @@ use nix::libc::{
     CLOCK_MONOTONIC_RAW, CLOCK_PROCESS_CPUTIME_ID, CLOCK_REALTIME, CLOCK_REALTIME_ALARM,
     CLOCK_REALTIME_COARSE, CLOCK_TAI, CLOCK_THREAD_CPUTIME_ID,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::warn;
 
 macro_rules! sys_num {
     (
         $($variant:ident => $val:expr,)+
     ) => {
-        #[derive(Copy, Clone, Debug)]
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
         pub enum SysNum {
             $($variant,)+
             Other(u64),
@@ -33,6 +33,24 @@ macro_rules! sys_num {
                 }
             }
         }
+
+        // Syscalls are named without underscores or case, e.g. "clock_gettime"
+        // or "ClockGetTime". Numbers are accepted too.
+        impl std::str::FromStr for SysNum {
+            type Err = String;
+
+            fn from_str(name: &str) -> Result<Self, Self::Err> {
+                let normalized = name.replace('_', "");
+                $(
+                    if normalized.eq_ignore_ascii_case(stringify!($variant)) {
+                        return Ok(SysNum::$variant);
+                    }
+                )+
+                name.parse::<u64>()
+                    .map(SysNum::from)
+                    .map_err(|_| format!("unknown syscall: {}", name))
+            }
+        }
     }
 }
 
@@ -44,6 +62,7 @@ sys_num!(
     Stat => 4,
     FStat => 5,
     LStat => 6,
+    Poll => 7,
     LSeek => 8,
     Mmap => 9,
     Mprotect => 10,
@@ -56,28 +75,43 @@ sys_num!(
     PRead => 17,
     PWrite => 18,
     Access => 21,
+    Select => 23,
     SchedYield => 24,
     MAdvise => 28,
     Dup => 32,
     Dup2 => 33,
     Nanosleep => 35,
     GetPID => 39,
-    Socket => 41,  // TODO?
-    Connect => 42,  // TODO?
+    Socket => 41,
+    Connect => 42,
+    Accept => 43,
+    SendTo => 44,
+    SendMsg => 46,
+    Bind => 49,
     GetPeerName => 52,  // TODO?
+    GetSockOpt => 55,
     Clone => 56,
     Fork => 57,
     VFork => 58,
     Execve => 59,
     Exit => 60,
     Wait => 61,
-    Kill => 62,  // TODO?
-    Uname => 63,  // TODO?
+    Kill => 62,
+    Uname => 63,
     Fcntl => 72,
+    Truncate => 76,
     GetCWD => 79,  // TODO?
     Chdir => 80,  // TODO?
+    Rename => 82,
+    Mkdir => 83,
+    Rmdir => 84,
     Creat => 85,
+    Link => 86,
+    Unlink => 87,
+    Symlink => 88,
     ReadLink => 89,
+    Chmod => 90,
+    GetTimeOfDay => 96,
     GetRLimit => 97,
     SysInfo => 99,  // TODO?
     GetUID => 102,
@@ -89,14 +123,21 @@ sys_num!(
     SetPGID => 109,
     GetPPID => 110,
     GetPGRP => 111,
+    GetGroups => 115,
+    GetResUID => 118,
+    GetResGID => 120,
     SigAltStack => 131,
     StatFS => 137,
     FStatFS => 138,
     ArchPRCTL => 158,
+    AdjTimex => 159,
     SetRLimit => 160,
     GetTID => 186,
     GetXAttr => 191,
     LGetXAttr => 192,
+    ListXAttr => 194,
+    LListXAttr => 195,
+    TKill => 200,
     Time => 201,
     Futex => 202,
     SchedSetAffinity => 203,
@@ -105,21 +146,48 @@ sys_num!(
     SetTIDAddress => 218,
     FAdvise => 221,
     ClockGetTime => 228,
+    ClockGetRes => 229,
+    ClockNanosleep => 230,
     ExitGroup => 231,
-    TGKill => 234,  // TODO?
+    EpollWait => 232,
+    TGKill => 234,
+    WaitId => 247,
     OpenAt => 257,
+    MkdirAt => 258,
     NewFstatAt => 262,
+    UnlinkAt => 263,
+    RenameAt => 264,
+    LinkAt => 265,
+    SymlinkAt => 266,
+    ReadLinkAt => 267,
+    FChmodAt => 268,
+    FAccessAt => 269,
+    PSelect6 => 270,
+    PPoll => 271,
     SetRobustList => 273,
     UTimeNsAt => 280,
+    EpollPWait => 281,
+    Accept4 => 288,
+    Dup3 => 292,
     Pipe2 => 293,
     PRLimit => 302,
+    NameToHandleAt => 303,
+    OpenByHandleAt => 304,
+    ClockAdjTime => 305,
+    SendMMsg => 307,
+    RenameAt2 => 316,
     GetRandom => 318,
+    ExecveAt => 322,
     StatX => 332,
     Rseq => 334,
+    Clone3 => 435,
+    CloseRange => 436,
+    OpenAt2 => 437,
     FAccessAt2 => 439,
+    EpollPWait2 => 441,
 );
 
-#[derive(Clone, Copy, Debug, Serialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Clock {
     Realtime(i32),
     Monotonic(i32),
@@ -2,15 +2,17 @@ mod operation;
 mod tracee;
 
 use crate::{
-    config::{Config, SpawnOptions},
-    modules::{FileManager, PIDManager, RandomManager, TimeManager},
-    Record, Recorder,
+    config::{Config, NetworkMode, SpawnOptions},
+    modules::{
+        DenyManager, DnsManager, FaultManager, FileManager, IdentityManager, NetManager,
+        PIDManager, RandomManager, StatManager, TimeManager, UnameManager,
+    },
+    Record, Recorder, Recording, SysNum,
 };
 use nix::{errno::Errno, sys::ptrace, unistd::Pid};
-use operation::Operation;
-pub use operation::OperationResult;
+pub use operation::{Operation, OperationResult};
 use std::{collections::HashMap, io::Result};
-pub use tracee::Tracee;
+pub use tracee::{Memory, Tracee};
 use tracing::debug;
 
 pub struct Tracer {
@@ -30,56 +32,216 @@ impl Tracer {
 
     pub fn run(&self, cfg: &Config) -> Result<()> {
         debug!("run");
+        self.trace(cfg, None)
+    }
+
+    pub fn replay(&self, cfg: &Config, recording: Recording) -> Result<()> {
+        debug!("replay");
+        self.trace(cfg, Some(recording))
+    }
+
+    fn trace(&self, cfg: &Config, mut recording: Option<Recording>) -> Result<()> {
+        let identity_mgr = IdentityManager::new(cfg.redirect.identity.clone())?;
         let mut files_redirect: HashMap<String, String> = cfg
             .redirect
             .files
             .iter()
             .map(|redirect| (redirect.from.clone(), redirect.to.clone()))
             .collect();
-
-        // Add default redirection for /dev/(u)random if randomness is redirected.
-        if cfg.redirect.random {
-            for key in ["/dev/urandom", "/dev/random"] {
-                if !files_redirect.contains_key(key) {
-                    debug!("redirecting {} to /dev/zero", key);
-                    files_redirect.insert(key.to_string(), "/dev/zero".to_string());
-                }
-            }
+        // Generated files don't replace those set in the configuration.
+        if let Some((from, to)) = identity_mgr.redirects() {
+            files_redirect.entry(from).or_insert(to);
+        }
+        let dns_mgr = DnsManager::new(cfg.redirect.dns.clone())?;
+        for (from, to) in dns_mgr.redirects() {
+            files_redirect.entry(from).or_insert(to);
+        }
+        let mut uname_mgr = match recording {
+            Some(ref mut recording) => UnameManager::replay(
+                cfg.redirect.uname.clone(),
+                std::mem::take(&mut recording.uname),
+            )?,
+            None => UnameManager::new(cfg.redirect.uname.clone())?,
+        };
+        for (from, to) in uname_mgr.redirects() {
+            files_redirect.entry(from).or_insert(to);
         }
 
         let mut recorder = Recorder::new(&cfg.record)?;
-        let mut random_mgr = RandomManager::new(cfg.redirect.random);
-        let time_mgr = TimeManager::new(cfg.redirect.time);
-        let file_mgr = FileManager::new(files_redirect);
-        let pid_mgr = PIDManager::new(cfg.redirect.pid);
-        let disable_vdso = cfg.record.time || cfg.redirect.time.is_some();
+        let replay = recording.is_some();
+        let (mut random_mgr, mut time_mgr, mut file_mgr, mut pid_mgr) = match recording {
+            Some(recording) => (
+                RandomManager::replay(recording.random, &cfg.redirect.random, &cfg.record),
+                TimeManager::replay(recording.time),
+                FileManager::replay(
+                    files_redirect,
+                    cfg.redirect.follow_symlinks,
+                    recording.files,
+                ),
+                PIDManager::replay(recording.pid),
+            ),
+            None => (
+                RandomManager::new(&cfg.redirect.random, &cfg.record),
+                TimeManager::new(cfg.redirect.time.clone()),
+                FileManager::new(files_redirect, cfg.redirect.follow_symlinks),
+                PIDManager::new(cfg.redirect.pid),
+            ),
+        };
+        let stat_mgr = StatManager::new(
+            cfg.redirect.timestamps.clone(),
+            cfg.redirect.follow_symlinks,
+            cfg.redirect.time.is_some(),
+        )?;
+        // The first matching redirection applies, so those set in the
+        // configuration come before the resolver's.
+        let mut network_redirect = cfg.redirect.network.clone();
+        network_redirect.extend(dns_mgr.network_redirects());
+        let mut net_mgr = NetManager::new(
+            &network_redirect,
+            cfg.network == NetworkMode::Offline,
+            cfg.record.network,
+        )?;
+        let deny_mgr = DenyManager::new(cfg.deny.clone(), cfg.redirect.follow_symlinks);
+        let mut fault_mgr = FaultManager::new(&cfg.faults, cfg.redirect.follow_symlinks);
+        let vdso = (replay || cfg.record.time || cfg.redirect.time.is_some()).then_some(cfg.vdso);
+
+        pid_mgr.spawn(self.pid.as_raw());
 
         loop {
-            match Tracee::wait(self.pid, disable_vdso) {
+            match Tracee::wait(self.pid, vdso) {
                 Ok(None) => {
                     debug!("command exited");
                     return Ok(());
                 }
+                Ok(Some((_, Operation::Spawn { child }))) => {
+                    pid_mgr.spawn(child);
+                }
+                Ok(Some((_, Operation::Exited { pid }))) => {
+                    pid_mgr.exit(pid);
+                    random_mgr.exit(pid);
+                }
+                Ok(Some((ref mut tracee, Operation::Return { num }))) => match num {
+                    SysNum::Accept | SysNum::Accept4 => {
+                        recorder.record(net_mgr.accept(tracee, num)?.into())?;
+                    }
+                    SysNum::Socket
+                    | SysNum::Bind
+                    | SysNum::Connect
+                    | SysNum::SendTo
+                    | SysNum::SendMsg
+                    | SysNum::SendMMsg => {
+                        if let Some(record) = net_mgr.result(tracee, num)? {
+                            recorder.record(record.into())?;
+                        }
+                    }
+                    _ => pid_mgr.result(tracee, num)?,
+                },
                 Ok(Some((ref mut tracee, operation))) => {
-                    let record: Record = match operation {
-                        Operation::Open { ref path, num, read, write } => {
-                            file_mgr.process(tracee, path, num, read, write)?.into()
+                    if let Some(record) = deny_mgr.process(tracee, &operation)? {
+                        recorder.record(record.into())?;
+                        continue;
+                    }
+                    if let Some(record) = fault_mgr.process(tracee, &operation)? {
+                        let skipped = record.errno.is_some();
+                        recorder.record(record.into())?;
+                        if skipped {
+                            continue;
+                        }
+                    }
+                    let record: Option<Record> = match operation {
+                        Operation::Open {
+                            ref path,
+                            num,
+                            read,
+                            write,
+                        } => Some(
+                            file_mgr
+                                .process(tracee, path, num, read, write, &mut pid_mgr)?
+                                .into(),
+                        ),
+                        Operation::Path { ref path, num } => {
+                            let record = file_mgr.lookup(tracee, path, num, &mut pid_mgr)?;
+                            stat_mgr.lookup(tracee, path, num, time_mgr.realtime())?;
+                            Some(record.into())
+                        }
+                        Operation::FStat { num, fd } => {
+                            stat_mgr.fstat(tracee, fd, num, time_mgr.realtime())?;
+                            None
+                        }
+                        Operation::Change {
+                            ref paths,
+                            num,
+                            ref target,
+                        } => Some(
+                            file_mgr
+                                .change(tracee, paths, num, target, &mut pid_mgr)?
+                                .into(),
+                        ),
+                        Operation::OpenByHandle { read, write } => file_mgr
+                            .open_by_handle(tracee, read, write)?
+                            .map(Into::into),
+                        Operation::Read { fd, addr } => {
+                            random_mgr.read(tracee, fd, addr)?.map(Into::into)
+                        }
+                        Operation::Close { fd } => {
+                            random_mgr.close(fd);
+                            None
                         }
                         Operation::Rand { len, addr } => {
-                            random_mgr.process(tracee, len, addr)?.into()
+                            Some(random_mgr.process(tracee, len, addr)?.into())
                         }
                         Operation::Time { num, clock, addr } => {
-                            time_mgr.process(tracee, num, clock, addr)?.into()
+                            Some(time_mgr.process(tracee, num, clock, addr)?.into())
+                        }
+                        Operation::ClockRes { clock, addr } => {
+                            time_mgr.resolution(tracee, clock, addr)?;
+                            None
+                        }
+                        Operation::Sleep {
+                            num,
+                            clock,
+                            timeout,
+                            absolute,
+                        } => {
+                            time_mgr.sleep(tracee, num, clock, timeout, absolute)?;
+                            None
+                        }
+                        Operation::Pid { num } => {
+                            let record = pid_mgr.process(tracee, num)?;
+                            identity_mgr.process(tracee, num)?;
+                            Some(record.into())
+                        }
+                        Operation::Uname { addr } => {
+                            uname_mgr.process(tracee, addr)?.map(Record::from)
+                        }
+                        Operation::Net { num } => net_mgr.process(tracee, num)?.map(Into::into),
+                        Operation::Identity { num } => {
+                            identity_mgr.process(tracee, num)?;
+                            None
+                        }
+                        Operation::Wait { num } | Operation::Kill { num } => {
+                            pid_mgr.translate_args(tracee, num)?;
+                            None
+                        }
+                        Operation::Fork { num } => {
+                            // The child is reported when it is created.
+                            debug!(syscall = ?num, "forking");
+                            None
                         }
-                        Operation::Pid { num } => pid_mgr.process(tracee, num)?.into(),
-                        op @ (Operation::Fork { .. } | Operation::Wait | Operation::Exit) => {
+                        Operation::Other => None,
+                        op @ (Operation::Spawn { .. }
+                        | Operation::Exited { .. }
+                        | Operation::Return { .. }
+                        | Operation::Exit) => {
                             unreachable!(
                                 "this operation type should not be returned here: {:?}",
                                 op
                             )
                         }
                     };
-                    recorder.record(record)?;
+                    if let Some(record) = record {
+                        recorder.record(record)?;
+                    }
                 }
                 Err(e) => panic!("unexpected error: {:?}", e),
             }
@@ -1,6 +1,16 @@
-use std::{io::Result, path::PathBuf};
+use std::{
+    io::Result,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use nix::errno::Errno;
+use nix::{
+    errno::Errno,
+    libc::{
+        AT_FDCWD, CLOCK_MONOTONIC, CLOCK_REALTIME, FUTEX_CLOCK_REALTIME, FUTEX_PRIVATE_FLAG,
+        FUTEX_WAIT, FUTEX_WAIT_BITSET, TIMER_ABSTIME, UIO_MAXIOV,
+    },
+};
 use tracing::{debug, warn};
 
 use super::tracee::Tracee;
@@ -17,6 +27,38 @@ pub enum Operation {
         read: bool,
         write: bool,
     },
+    // Path lookups that don't open the file.
+    Path {
+        num: SysNum,
+        path: PathBuf,
+    },
+    // Syscalls modifying the file system. Paths are listed along with the
+    // index of the argument holding them.
+    Change {
+        num: SysNum,
+        paths: Vec<(u8, PathBuf)>,
+        // Content of a new symlink, which is not resolved.
+        target: Option<String>,
+    },
+    // Stat of an open file.
+    FStat {
+        num: SysNum,
+        fd: u64,
+    },
+    // Opens through a file handle, whose path is only known afterwards.
+    OpenByHandle {
+        read: bool,
+        write: bool,
+    },
+    Read {
+        fd: u64,
+        addr: u64,
+    },
+    // Syscalls closing a file descriptor, or possibly any of them for
+    // close_range() and exec.
+    Close {
+        fd: Option<u64>,
+    },
     Rand {
         len: usize,
         addr: u64,
@@ -26,11 +68,55 @@ pub enum Operation {
         clock: Clock,
         addr: u64,
     },
+    ClockRes {
+        clock: Clock,
+        addr: u64,
+    },
+    // A syscall blocking for at most a timeout, or until a deadline on the
+    // given clock.
+    Sleep {
+        num: SysNum,
+        clock: Clock,
+        timeout: Duration,
+        absolute: bool,
+    },
     Pid {
         num: SysNum,
     },
-    Wait,
+    // Lookups of the user and groups that write them to memory.
+    Identity {
+        num: SysNum,
+    },
+    // uname(), writing a struct utsname at addr.
+    Uname {
+        addr: u64,
+    },
+    // Socket creation, and syscalls taking a socket address.
+    Net {
+        num: SysNum,
+    },
+    // A new process or thread, reported to its parent before it runs.
+    Spawn {
+        child: i32,
+    },
+    // A process or thread that is gone, not yet reaped for processes.
+    Exited {
+        pid: i32,
+    },
+    // Result of a syscall that could block until other tracees run, so it
+    // can't be stepped over when it starts.
+    Return {
+        num: SysNum,
+    },
+    Wait {
+        num: SysNum,
+    },
+    Kill {
+        num: SysNum,
+    },
     Exit,
+    // Any other syscall, without a dedicated operation.
+    Other,
 }
 
 #[derive(Debug)]
@@ -46,8 +132,9 @@ impl Operation {
         match registers.orig_rax.into() {
             // Open
             SysNum::Open => {
-                let path = tracee.read_string(registers.rdi)?;
-                let path = PathBuf::from(path);
+                let Some(path) = Self::read_path(tracee, AT_FDCWD as u64, registers.rdi) else {
+                    return Ok(None);
+                };
                 let rw_flags = registers.rsi & 0b11;
                 Ok(Some(Operation::Open {
                     path,
@@ -57,10 +144,9 @@ impl Operation {
                 }))
             }
             SysNum::OpenAt => {
-                // For now we only handle the case where the first argument is AT_FDCWD.
-                assert_eq!(registers.rdi as i32, -100);
-                let path = tracee.read_string(registers.rsi)?;
-                let path = PathBuf::from(path);
+                let Some(path) = Self::read_path(tracee, registers.rdi, registers.rsi) else {
+                    return Ok(None);
+                };
                 let rw_flags = registers.rdx & 0b11;
                 Ok(Some(Operation::Open {
                     path,
@@ -69,6 +155,173 @@ impl Operation {
                     write: rw_flags != 0,
                 }))
             }
+            SysNum::Creat => {
+                let Some(path) = Self::read_path(tracee, AT_FDCWD as u64, registers.rdi) else {
+                    return Ok(None);
+                };
+                Ok(Some(Operation::Open {
+                    path,
+                    num: SysNum::Creat,
+                    read: false,
+                    write: true,
+                }))
+            }
+            SysNum::OpenAt2 => {
+                let Some(path) = Self::read_path(tracee, registers.rdi, registers.rsi) else {
+                    return Ok(None);
+                };
+                // Flags are the first field of struct open_how.
+                let flags = match tracee.read_memory(registers.rdx, 8) {
+                    Ok(data) => u64::from_ne_bytes(data[0..8].try_into().unwrap()),
+                    Err(e) => {
+                        warn!("failed to read open_how: {}", e);
+                        return Ok(None);
+                    }
+                };
+                let rw_flags = flags & 0b11;
+                Ok(Some(Operation::Open {
+                    path,
+                    num: SysNum::OpenAt2,
+                    read: rw_flags != 1,
+                    write: rw_flags != 0,
+                }))
+            }
+            SysNum::NameToHandleAt => {
+                let Some(path) = Self::read_path(tracee, registers.rdi, registers.rsi) else {
+                    return Ok(None);
+                };
+                Ok(Some(Operation::Open {
+                    path,
+                    num: SysNum::NameToHandleAt,
+                    read: false,
+                    write: false,
+                }))
+            }
+            SysNum::OpenByHandleAt => {
+                let rw_flags = registers.rdx & 0b11;
+                Ok(Some(Operation::OpenByHandle {
+                    read: rw_flags != 1,
+                    write: rw_flags != 0,
+                }))
+            }
+            SysNum::FStat => Ok(Some(Operation::FStat {
+                num: SysNum::FStat,
+                fd: registers.rdi,
+            })),
+            // Path lookups
+            num @ (SysNum::Stat
+            | SysNum::LStat
+            | SysNum::Access
+            | SysNum::ReadLink
+            | SysNum::StatFS
+            | SysNum::GetXAttr
+            | SysNum::LGetXAttr
+            | SysNum::ListXAttr
+            | SysNum::LListXAttr) => Ok(Self::read_path(tracee, AT_FDCWD as u64, registers.rdi)
+                .map(|path| Operation::Path { num, path })),
+            num @ (SysNum::NewFstatAt
+            | SysNum::StatX
+            | SysNum::FAccessAt
+            | SysNum::FAccessAt2
+            | SysNum::ReadLinkAt) => {
+                // An empty path refers to the directory fd itself (AT_EMPTY_PATH).
+                if tracee.read_memory(registers.rsi, 1).ok() == Some(vec![0]) {
+                    if matches!(num, SysNum::NewFstatAt | SysNum::StatX) {
+                        return Ok(Some(Operation::FStat {
+                            num,
+                            fd: registers.rdi,
+                        }));
+                    }
+                    return Ok(None);
+                }
+                Ok(Self::read_path(tracee, registers.rdi, registers.rsi)
+                    .map(|path| Operation::Path { num, path }))
+            }
+            // File system changes
+            num @ (SysNum::Unlink
+            | SysNum::Rmdir
+            | SysNum::Mkdir
+            | SysNum::Chmod
+            | SysNum::Truncate) => Ok(Self::read_path(tracee, AT_FDCWD as u64, registers.rdi).map(
+                |path| Operation::Change {
+                    num,
+                    paths: vec![(1, path)],
+                    target: None,
+                },
+            )),
+            num @ (SysNum::UnlinkAt | SysNum::MkdirAt | SysNum::FChmodAt | SysNum::UTimeNsAt) => {
+                // utimensat() on the directory fd itself passes a null path.
+                if registers.rsi == 0 {
+                    return Ok(None);
+                }
+                Ok(
+                    Self::read_path(tracee, registers.rdi, registers.rsi).map(|path| {
+                        Operation::Change {
+                            num,
+                            paths: vec![(2, path)],
+                            target: None,
+                        }
+                    }),
+                )
+            }
+            num @ (SysNum::Rename | SysNum::Link) => {
+                let (Some(from), Some(to)) = (
+                    Self::read_path(tracee, AT_FDCWD as u64, registers.rdi),
+                    Self::read_path(tracee, AT_FDCWD as u64, registers.rsi),
+                ) else {
+                    return Ok(None);
+                };
+                Ok(Some(Operation::Change {
+                    num,
+                    paths: vec![(1, from), (2, to)],
+                    target: None,
+                }))
+            }
+            num @ (SysNum::RenameAt | SysNum::RenameAt2 | SysNum::LinkAt) => {
+                let (Some(from), Some(to)) = (
+                    Self::read_path(tracee, registers.rdi, registers.rsi),
+                    Self::read_path(tracee, registers.rdx, registers.r10),
+                ) else {
+                    return Ok(None);
+                };
+                Ok(Some(Operation::Change {
+                    num,
+                    paths: vec![(2, from), (4, to)],
+                    target: None,
+                }))
+            }
+            num @ (SysNum::Symlink | SysNum::SymlinkAt) => {
+                let (dirfd, arg, addr) = match num {
+                    SysNum::Symlink => (AT_FDCWD as u64, 2, registers.rsi),
+                    _ => (registers.rsi, 3, registers.rdx),
+                };
+                let (Ok(target), Some(path)) = (
+                    tracee.read_string(registers.rdi),
+                    Self::read_path(tracee, dirfd, addr),
+                ) else {
+                    return Ok(None);
+                };
+                Ok(Some(Operation::Change {
+                    num,
+                    paths: vec![(arg, path)],
+                    target: Some(target),
+                }))
+            }
+            // Read
+            SysNum::Read | SysNum::PRead => Ok(Some(Operation::Read {
+                fd: registers.rdi,
+                addr: registers.rsi,
+            })),
+            // Close
+            SysNum::Close => Ok(Some(Operation::Close {
+                fd: Some(registers.rdi),
+            })),
+            SysNum::Dup2 | SysNum::Dup3 => Ok(Some(Operation::Close {
+                fd: Some(registers.rsi),
+            })),
+            SysNum::CloseRange | SysNum::Execve | SysNum::ExecveAt => {
+                Ok(Some(Operation::Close { fd: None }))
+            }
             // Rand
             SysNum::GetRandom => {
                 let len = registers.rsi as usize;
@@ -84,29 +337,116 @@ impl Operation {
                     clock: registers.rdi.into(),
                 }))
             }
-            SysNum::Time => {
-                let num = SysNum::Time;
+            num @ (SysNum::Time | SysNum::GetTimeOfDay | SysNum::AdjTimex) => {
                 Ok(Some(Operation::Time {
                     num,
                     addr: registers.rdi,
                     clock: Clock::Realtime(0),
                 }))
             }
+            SysNum::ClockAdjTime => {
+                let num = SysNum::ClockAdjTime;
+                Ok(Some(Operation::Time {
+                    num,
+                    addr: registers.rsi,
+                    clock: registers.rdi.into(),
+                }))
+            }
+            SysNum::ClockGetRes => Ok(Some(Operation::ClockRes {
+                addr: registers.rsi,
+                clock: registers.rdi.into(),
+            })),
+            // Sleep
+            SysNum::Nanosleep => Ok(Self::read_timeout(tracee, registers.rdi, false)
+                .map(|timeout| Operation::relative_sleep(SysNum::Nanosleep, timeout))),
+            SysNum::ClockNanosleep => Ok(Self::read_timeout(tracee, registers.rdx, false).map(
+                |timeout| Operation::Sleep {
+                    num: SysNum::ClockNanosleep,
+                    clock: registers.rdi.into(),
+                    timeout,
+                    absolute: registers.rsi as i32 & TIMER_ABSTIME != 0,
+                },
+            )),
+            // Negative timeouts wait forever, null ones don't wait.
+            num @ SysNum::Poll if (registers.rdx as i32) > 0 => Ok(Some(
+                Operation::relative_sleep(num, Duration::from_millis(registers.rdx as i32 as u64)),
+            )),
+            num @ (SysNum::EpollWait | SysNum::EpollPWait) if (registers.r10 as i32) > 0 => {
+                Ok(Some(Operation::relative_sleep(
+                    num,
+                    Duration::from_millis(registers.r10 as i32 as u64),
+                )))
+            }
+            num @ SysNum::PPoll => Ok(Self::read_timeout(tracee, registers.rdx, false)
+                .map(|timeout| Operation::relative_sleep(num, timeout))),
+            num @ SysNum::EpollPWait2 => Ok(Self::read_timeout(tracee, registers.r10, false)
+                .map(|timeout| Operation::relative_sleep(num, timeout))),
+            num @ (SysNum::Select | SysNum::PSelect6) => {
+                let micros = num == SysNum::Select;
+                Ok(Self::read_timeout(tracee, registers.r8, micros)
+                    .map(|timeout| Operation::relative_sleep(num, timeout)))
+            }
+            SysNum::Futex => {
+                let op = registers.rsi as i32;
+                let absolute = match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+                    FUTEX_WAIT => false,
+                    FUTEX_WAIT_BITSET => true,
+                    _ => return Ok(None),
+                };
+                let clock = if op & FUTEX_CLOCK_REALTIME != 0 {
+                    CLOCK_REALTIME
+                } else {
+                    CLOCK_MONOTONIC
+                };
+                Ok(
+                    Self::read_timeout(tracee, registers.r10, false).map(|timeout| {
+                        Operation::Sleep {
+                            num: SysNum::Futex,
+                            clock: (clock as u64).into(),
+                            timeout,
+                            absolute,
+                        }
+                    }),
+                )
+            }
             num @ (SysNum::GetPID
             | SysNum::GetPPID
+            | SysNum::GetTID
             | SysNum::GetGID
             | SysNum::GetEGID
             | SysNum::GetUID
             | SysNum::GetEUID) => Ok(Some(Operation::Pid { num })),
+            num @ (SysNum::GetGroups | SysNum::GetResUID | SysNum::GetResGID) => {
+                Ok(Some(Operation::Identity { num }))
+            }
+            SysNum::Uname => Ok(Some(Operation::Uname {
+                addr: registers.rdi,
+            })),
+            // Network
+            num @ (SysNum::Socket | SysNum::Connect | SysNum::Bind) => {
+                Ok(Some(Operation::Net { num }))
+            }
+            // Sends on connected sockets have no address.
+            num @ SysNum::SendTo if registers.r8 != 0 => Ok(Some(Operation::Net { num })),
+            num @ SysNum::SendMsg if Self::has_names(tracee, registers.rsi, 1) => {
+                Ok(Some(Operation::Net { num }))
+            }
+            num @ SysNum::SendMMsg if Self::has_names(tracee, registers.rsi, registers.rdx) => {
+                Ok(Some(Operation::Net { num }))
+            }
             // Fork
-            num @ (SysNum::Clone | SysNum::Fork | SysNum::VFork) => {
+            num @ (SysNum::Clone | SysNum::Clone3 | SysNum::Fork | SysNum::VFork) => {
                 debug!("fork-like operation");
                 Ok(Some(Operation::Fork { num }))
             }
             // Wait
-            SysNum::Wait => {
+            num @ (SysNum::Wait | SysNum::WaitId) => {
                 debug!("process waits for child");
-                Ok(Some(Operation::Wait))
+                Ok(Some(Operation::Wait { num }))
+            }
+            // Signals
+            num @ (SysNum::Kill | SysNum::TKill | SysNum::TGKill) => {
+                Ok(Some(Operation::Kill { num }))
             }
             // Exit
             SysNum::ExitGroup | SysNum::Exit => Ok(Some(Operation::Exit)),
@@ -123,6 +463,90 @@ impl Operation {
         }
     }
 
+    // Paths given to the syscall, as seen by the tracee.
+    pub fn paths(&self) -> Vec<&Path> {
+        match self {
+            Operation::Open { path, .. } | Operation::Path { path, .. } => vec![path.as_path()],
+            Operation::Change { paths, .. } => {
+                paths.iter().map(|(_, path)| path.as_path()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    // Read a path argument, resolving it against the directory fd when it is
+    // relative. Paths relative to the working directory are left as is.
+    // Failures are logged, and the syscall is then not intercepted.
+    fn read_path(tracee: &Tracee, dirfd: u64, addr: u64) -> Option<PathBuf> {
+        let path = match tracee.read_string(addr) {
+            Ok(path) => PathBuf::from(path),
+            Err(e) => {
+                warn!(addr, "failed to read path: {}", e);
+                return None;
+            }
+        };
+        if path.is_absolute() || dirfd as i32 == AT_FDCWD {
+            return Some(path);
+        }
+        match tracee.fd_path(dirfd) {
+            Ok(dir) => Some(dir.join(path)),
+            Err(e) => {
+                warn!(dirfd, ?path, "failed to resolve directory fd: {}", e);
+                None
+            }
+        }
+    }
+
+    // A sleep for a duration, which doesn't depend on the clock.
+    fn relative_sleep(num: SysNum, timeout: Duration) -> Operation {
+        Operation::Sleep {
+            num,
+            clock: (CLOCK_MONOTONIC as u64).into(),
+            timeout,
+            absolute: false,
+        }
+    }
+
+    // Read a struct timespec, or a struct timeval if `micros` is set. Null
+    // pointers mean there is no timeout, and invalid ones are left to the
+    // kernel, which rejects them.
+    fn read_timeout(tracee: &Tracee, addr: u64, micros: bool) -> Option<Duration> {
+        if addr == 0 {
+            return None;
+        }
+        let data = match tracee.read_memory(addr, 16) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("failed to read timeout: {}", e);
+                return None;
+            }
+        };
+        let secs = i64::from_ne_bytes(data[0..8].try_into().unwrap());
+        let subsec = i64::from_ne_bytes(data[8..16].try_into().unwrap());
+        let limit = if micros { 1_000_000 } else { 1_000_000_000 };
+        if secs < 0 || !(0..limit).contains(&subsec) {
+            return None;
+        }
+        let subsec = if micros {
+            Duration::from_micros(subsec as u64)
+        } else {
+            Duration::from_nanos(subsec as u64)
+        };
+        Duration::from_secs(secs as u64).checked_add(subsec)
+    }
+
+    // Whether any of the `count` messages, in an array of struct mmsghdr or a
+    // single struct msghdr, has a destination address in msg_name.
+    fn has_names(tracee: &Tracee, addr: u64, count: u64) -> bool {
+        // Size of struct mmsghdr, a struct msghdr followed by the length sent.
+        const MMSGHDR_SIZE: u64 = 64;
+        (0..count.min(UIO_MAXIOV as u64)).any(|index| {
+            tracee
+                .read_memory(addr + index * MMSGHDR_SIZE, 8)
+                .is_ok_and(|name| name.iter().any(|&byte| byte != 0))
+        })
+    }
+
     pub fn result(retval: i64) -> OperationResult {
         if retval < 0 {
             OperationResult::Error(Errno::from_raw(-retval as i32))
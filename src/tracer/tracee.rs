@@ -1,5 +1,8 @@
 use super::{Operation, OperationResult};
-use crate::{config::SpawnOptions, syscall::SysNum};
+use crate::{
+    config::{SpawnOptions, VdsoMode},
+    syscall::SysNum,
+};
 use nix::{
     errno::Errno,
     libc::{
@@ -9,12 +12,14 @@ use nix::{
     },
     sys::{
         ptrace,
+        signal::Signal,
         wait::{waitpid, WaitPidFlag, WaitStatus},
     },
     unistd::{getpid, setsid, Pid},
 };
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Error, Result};
 use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use tracing::{debug, info, warn};
 
 #[derive(Copy, Clone, Debug)]
@@ -32,8 +37,9 @@ pub struct Tracee {
     allocations: Option<Vec<Memory>>,
 }
 
+// Memory reserved in the tracee.
 #[derive(Debug)]
-struct Memory {
+pub struct Memory {
     addr: u64,
     len: usize,
 }
@@ -72,7 +78,7 @@ impl Tracee {
         Self {
             pid,
             registers,
-            state: if Errno::from_raw(-(registers.rax as i32)) == Errno::ENOSYS {
+            state: if Errno::from_raw((registers.rax as i32).wrapping_neg()) == Errno::ENOSYS {
                 State::BeforeSyscall
             } else {
                 State::AfterSyscall
@@ -81,6 +87,17 @@ impl Tracee {
         }
     }
 
+    // A tracee that is gone, without registers to read.
+    fn exited(pid: Pid) -> Self {
+        Self {
+            pid,
+            // Plain integers.
+            registers: unsafe { std::mem::zeroed() },
+            state: State::Exited,
+            allocations: None,
+        }
+    }
+
     pub fn pid(&self) -> i32 {
         self.pid.as_raw()
     }
@@ -89,6 +106,25 @@ impl Tracee {
         self.registers
     }
 
+    // Thread group id, i.e. the pid of the process this thread belongs to.
+    pub fn tgid(&self) -> Result<i32> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.pid.as_raw()))?;
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Tgid:"))
+            .and_then(|tgid| tgid.trim().parse().ok())
+            .ok_or_else(|| Error::other("no Tgid in process status"))
+    }
+
+    pub fn cwd(&self) -> Result<PathBuf> {
+        std::fs::read_link(format!("/proc/{}/cwd", self.pid.as_raw()))
+    }
+
+    // Path of the file behind one of the tracee's file descriptors.
+    pub fn fd_path(&self, fd: u64) -> Result<PathBuf> {
+        std::fs::read_link(format!("/proc/{}/fd/{}", self.pid.as_raw(), fd as i32))
+    }
+
     fn resume(&self) {
         match ptrace::syscall(self.pid, None) {
             Ok(_) => (),
@@ -139,6 +175,27 @@ impl Tracee {
         Ok(())
     }
 
+    // Skip the syscall the tracee is about to make, and make it return `result`
+    // instead. The kernel sees an invalid syscall number.
+    pub fn skip_syscall(&mut self, result: u64) -> Result<()> {
+        match self.state {
+            State::BeforeSyscall => {
+                debug!(result, "skipping syscall");
+                let mut registers = self.registers();
+                registers.orig_rax = u64::MAX;
+                self.set_registers(registers)?;
+                self.step_syscall_and_wait()?;
+                if let State::Exited = self.state {
+                    return Ok(());
+                }
+                // The result is -ENOSYS, which looks like a syscall-enter-stop.
+                self.state = State::AfterSyscall;
+                self.set_result(result)
+            }
+            State::AfterSyscall | State::Exited => Err(Error::other("invalid state")),
+        }
+    }
+
     pub fn get_result(&mut self) -> Result<OperationResult> {
         // Make sure we are in the proper state.
         match self.state {
@@ -147,12 +204,7 @@ impl Tracee {
                 self.step_over_syscall()?;
             }
             State::AfterSyscall => {}
-            State::Exited => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "invalid state: process has exited",
-                ))
-            }
+            State::Exited => return Err(Error::other("invalid state: process has exited")),
         }
 
         // Read the syscall result.
@@ -183,12 +235,7 @@ impl Tracee {
                     debug!(?pid, "ptrace event received while waiting for syscall");
                     continue;
                 }
-                e => {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("unexpected wait status: {:?}", e),
-                    ))
-                }
+                e => return Err(Error::other(format!("unexpected wait status: {:?}", e))),
             }
         }
     }
@@ -202,9 +249,7 @@ impl Tracee {
                 assert!(matches!(self.state, State::AfterSyscall | State::Exited));
                 Ok(())
             }
-            State::AfterSyscall | State::Exited => {
-                Err(Error::new(ErrorKind::Other, "invalid state"))
-            }
+            State::AfterSyscall | State::Exited => Err(Error::other("invalid state")),
         }
     }
 
@@ -217,9 +262,7 @@ impl Tracee {
                 assert!(matches!(self.state, State::BeforeSyscall));
                 Ok(())
             }
-            State::BeforeSyscall | State::Exited => {
-                Err(Error::new(ErrorKind::Other, "invalid state"))
-            }
+            State::BeforeSyscall | State::Exited => Err(Error::other("invalid state")),
         }
     }
 
@@ -301,13 +344,13 @@ impl Tracee {
 
                 result
             }
-            State::Exited => return Err(Error::new(ErrorKind::Other, "invalid state")),
+            State::Exited => return Err(Error::other("invalid state")),
         };
 
         if result < 0 {
             let err = Errno::from_raw(-result as i32);
             warn!(?err, "syscall error");
-            return Err(Error::new(ErrorKind::Other, err));
+            return Err(Error::other(err));
         }
         Ok(result as u64)
     }
@@ -354,6 +397,24 @@ impl Tracee {
         Ok(addr)
     }
 
+    pub fn write_data(&mut self, data: &[u8]) -> Result<u64> {
+        let addr = self.reserve_memory(data.len())?;
+        self.write_bytes(addr, data)?;
+        debug!(addr, "wrote data in tracee");
+        Ok(addr)
+    }
+
+    // Keep the memory written so far past this stop, for syscalls whose
+    // result is only read when they return.
+    pub fn keep_memory(&mut self) -> Vec<Memory> {
+        self.allocations.take().unwrap_or_default()
+    }
+
+    // Free memory kept from an earlier stop along with this one's.
+    pub fn release_memory(&mut self, memory: Vec<Memory>) {
+        self.allocations.get_or_insert_with(Vec::new).extend(memory);
+    }
+
     pub fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
         // Optim for reading small amount of data
         if len <= 8 {
@@ -400,7 +461,7 @@ impl Tracee {
         Ok(result)
     }
 
-    pub fn wait(parent: Pid, disable_vdso: bool) -> Result<Option<(Tracee, Operation)>> {
+    pub fn wait(parent: Pid, vdso: Option<VdsoMode>) -> Result<Option<(Tracee, Operation)>> {
         let group = Pid::from_raw(-parent.as_raw());
         loop {
             match waitpid(group, Some(WaitPidFlag::__WALL)) {
@@ -409,7 +470,16 @@ impl Tracee {
                     if pid == parent {
                         return Ok(None);
                     }
-                    continue;
+                    let operation = Operation::Exited { pid: pid.as_raw() };
+                    return Ok(Some((Tracee::exited(pid), operation)));
+                }
+                Ok(WaitStatus::Signaled(pid, signal, _)) => {
+                    info!(?pid, ?signal, "child killed");
+                    if pid == parent {
+                        return Ok(None);
+                    }
+                    let operation = Operation::Exited { pid: pid.as_raw() };
+                    return Ok(Some((Tracee::exited(pid), operation)));
                 }
                 Ok(WaitStatus::PtraceSyscall(pid)) => {
                     // A tracee is ready.
@@ -418,34 +488,62 @@ impl Tracee {
                     let _span = tracing::span!(tracing::Level::INFO, "tracee", pid = tracee.pid())
                         .entered();
                     if let State::AfterSyscall = tracee.state {
-                        // We get the result of a syscall we didn't bother checking
                         let syscall = SysNum::from(tracee.registers().orig_rax);
+                        // Some operations could block the tracee until the new process does
+                        // something, or another tracee, their result is only seen here.
+                        if matches!(
+                            syscall,
+                            SysNum::Clone
+                                | SysNum::Clone3
+                                | SysNum::Fork
+                                | SysNum::VFork
+                                | SysNum::Wait
+                                | SysNum::WaitId
+                                | SysNum::Accept
+                                | SysNum::Accept4
+                                | SysNum::Socket
+                                | SysNum::Bind
+                                | SysNum::Connect
+                                | SysNum::SendTo
+                                | SysNum::SendMsg
+                                | SysNum::SendMMsg
+                        ) {
+                            return Ok(Some((tracee, Operation::Return { num: syscall })));
+                        }
+                        // We get the result of a syscall we didn't bother checking
                         debug!(?syscall, "ignored result");
                         continue;
                     }
                     let operation = Operation::parse(&mut tracee)?;
                     if let Some(operation) = operation {
-                        // Some operations could block the tracee until the new process does something.
-                        // For now these operations never reach the client.
-                        if let Operation::Fork { .. } | Operation::Wait | Operation::Exit =
-                            operation
-                        {
+                        if let Operation::Exit = operation {
                             debug!(?operation, "ignoring");
                             continue;
                         }
                         return Ok(Some((tracee, operation)));
                     } else {
-                        // Syscall not supported, keep going.
-                        continue;
+                        // Syscall not supported, it may still be denied.
+                        return Ok(Some((tracee, Operation::Other)));
                     }
                 }
                 Ok(WaitStatus::PtraceEvent(pid, _, event)) => {
                     match event {
                         PTRACE_EVENT_CLONE | PTRACE_EVENT_FORK | PTRACE_EVENT_VFORK => {
-                            info!(?pid, "process is forking")
+                            let child = ptrace::getevent(pid)? as i32;
+                            info!(?pid, child, "process is forking");
+                            let tracee = Tracee::new(pid, ptrace::getregs(pid)?);
+                            return Ok(Some((tracee, Operation::Spawn { child })));
                         }
                         PTRACE_EVENT_VFORK_DONE => info!(?pid, "vfork done"),
-                        PTRACE_EVENT_EXEC => info!(?pid, "execing"),
+                        PTRACE_EVENT_EXEC => {
+                            info!(?pid, "execing");
+                            // The new program gets a new vDSO.
+                            if let Some(mode) = vdso {
+                                let tracee = Tracee::new(pid, ptrace::getregs(pid)?);
+                                tracee.intercept_vdso(mode)?;
+                                continue;
+                            }
+                        }
                         PTRACE_EVENT_EXIT => info!(?pid, "exiting"),
                         _ => warn!(event, "unsupported ptrace event"),
                     }
@@ -453,13 +551,15 @@ impl Tracee {
                     ptrace::syscall(pid, None)?;
                     continue;
                 }
-                Ok(WaitStatus::Stopped(pid, _)) => {
+                Ok(WaitStatus::Stopped(pid, signal)) => {
                     info!(?pid, "process starts");
                     // Configure the child process and resume it.
                     ptrace::setoptions(pid, ptrace::Options::all())?;
-                    if disable_vdso {
+                    // Only the first process stops right after exec(), forked
+                    // ones share the vDSO their parent already resolved.
+                    if let (Some(mode), Signal::SIGTRAP) = (vdso, signal) {
                         let tracee = Tracee::new(pid, ptrace::getregs(pid)?);
-                        tracee.disable_vdso()?;
+                        tracee.intercept_vdso(mode)?;
                     } else {
                         ptrace::syscall(pid, None)?;
                     }
@@ -472,9 +572,39 @@ impl Tracee {
         }
     }
 
-    fn disable_vdso(&self) -> Result<()> {
+    fn intercept_vdso(&self, mode: VdsoMode) -> Result<()> {
+        let Some(entry) = self.vdso_entry()? else {
+            debug!("no vDSO");
+            return Ok(());
+        };
+        match mode {
+            VdsoMode::Disable => {
+                info!("disabling vDSO");
+                self.disable_vdso(entry)?;
+            }
+            VdsoMode::Patch => {
+                let base = ptrace::read(self.pid, (entry + 8) as *mut c_void)? as u64;
+                info!(base, "patching vDSO");
+                if let Err(e) = self.patch_vdso(base) {
+                    warn!("failed to patch the vDSO, disabling it: {}", e);
+                    self.disable_vdso(entry)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn disable_vdso(&self, entry: u64) -> Result<()> {
+        unsafe {
+            ptrace::write(self.pid, entry as *mut c_void, AT_IGNORE as *mut c_void)?;
+        }
+        Ok(())
+    }
+
+    // Address of the AT_SYSINFO_EHDR entry in the auxiliary vector, which is
+    // on the stack right after exec().
+    fn vdso_entry(&self) -> Result<Option<u64>> {
         // inspired by https://github.com/danteu/novdso/
-        info!("disabling vDSO");
         let mut addr = self.registers().rsp;
         let mut count = 2;
         while count > 0 {
@@ -485,24 +615,124 @@ impl Tracee {
         }
         loop {
             match ptrace::read(self.pid, addr as *mut c_void)? as u64 {
-                AT_NULL => break,
+                AT_NULL => return Ok(None),
                 AT_SYSINFO_EHDR => {
                     debug!("found vDSO");
-                    // disable vDSO
-                    unsafe {
-                        ptrace::write(self.pid, addr as *mut c_void, AT_IGNORE as *mut c_void)?;
-                    }
-                    break;
+                    return Ok(Some(addr));
                 }
                 _ => {
                     addr += 16;
                 }
             }
         }
+    }
+
+    // Replace the time functions of the vDSO with plain syscalls, by looking
+    // them up in its dynamic symbol table. The image is checked as it is
+    // parsed, so that a malformed one is reported instead of panicking.
+    fn patch_vdso(&self, base: u64) -> Result<()> {
+        let header = self.read_memory(base, 64)?;
+        if header[0..4] != *b"\x7fELF" {
+            return Err(invalid_vdso());
+        }
+        let phoff = u64_at(&header, 0x20)? as usize;
+        let shoff = u64_at(&header, 0x28)? as usize;
+        let phnum = u16_at(&header, 0x38)? as usize;
+        let shnum = u16_at(&header, 0x3c)? as usize;
+        // Section headers are at the end of the image.
+        let size = shoff
+            .checked_add(shnum * 64)
+            .filter(|&size| size <= MAX_VDSO_SIZE)
+            .ok_or_else(invalid_vdso)?;
+        let image = self.read_memory(base, size)?;
+        let at = |offset: usize| image.get(offset..).ok_or_else(invalid_vdso);
+
+        // Symbols are relative to the first loaded segment.
+        let mut load = None;
+        for index in 0..phnum {
+            let header = at(phoff.saturating_add(index * 56))?;
+            if u32_at(header, 0)? == PT_LOAD {
+                load = Some(u64_at(header, 0x10)?.wrapping_sub(u64_at(header, 0x08)?));
+                break;
+            }
+        }
+        let load = load.ok_or_else(|| Error::other("no loaded segment in vDSO"))?;
+
+        let mut patched = Vec::new();
+        for index in 0..shnum {
+            let section = at(shoff + index * 64)?;
+            if u32_at(section, 0x04)? != SHT_DYNSYM {
+                continue;
+            }
+            let (offset, size) = (
+                u64_at(section, 0x18)? as usize,
+                u64_at(section, 0x20)? as usize,
+            );
+            let link = u32_at(section, 0x28)? as usize;
+            let strtab = u64_at(at(shoff.saturating_add(link * 64))?, 0x18)? as usize;
+            let symbols = offset
+                .checked_add(size)
+                .and_then(|end| image.get(offset..end))
+                .ok_or_else(invalid_vdso)?;
+            for symbol in symbols.chunks_exact(24) {
+                let name = at(strtab.saturating_add(u32_at(symbol, 0)? as usize))?;
+                let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(0)];
+                let num = match name.strip_prefix(b"__vdso_").unwrap_or(name) {
+                    b"clock_gettime" => SysNum::ClockGetTime,
+                    b"gettimeofday" => SysNum::GetTimeOfDay,
+                    b"time" => SysNum::Time,
+                    b"clock_getres" => SysNum::ClockGetRes,
+                    _ => continue,
+                };
+                let value = u64_at(symbol, 0x08)?;
+                if value == 0 {
+                    continue;
+                }
+                let addr = value
+                    .checked_sub(load)
+                    .filter(|&offset| offset < image.len() as u64)
+                    .ok_or_else(invalid_vdso)?
+                    + base;
+                if patched.contains(&addr) {
+                    continue;
+                }
+                // mov eax, num; syscall; ret
+                let mut code = vec![0xb8];
+                code.extend_from_slice(&(u64::from(num) as u32).to_ne_bytes());
+                code.extend_from_slice(&[0x0f, 0x05, 0xc3]);
+                debug!(?num, addr, "patching vDSO function");
+                self.write_bytes(addr, &code)?;
+                patched.push(addr);
+            }
+        }
         Ok(())
     }
 }
 
+const PT_LOAD: u32 = 1;
+const SHT_DYNSYM: u32 = 11;
+// The vDSO takes a few pages, anything much larger is not one.
+const MAX_VDSO_SIZE: usize = 1 << 20;
+
+fn invalid_vdso() -> Error {
+    Error::other("invalid vDSO image")
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data.get(offset..offset + 2).ok_or_else(invalid_vdso)?;
+    Ok(u16::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data.get(offset..offset + 4).ok_or_else(invalid_vdso)?;
+    Ok(u32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes = data.get(offset..offset + 8).ok_or_else(invalid_vdso)?;
+    Ok(u64::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
 impl Drop for Tracee {
     fn drop(&mut self) {
         // free reserved memory
@@ -512,6 +742,8 @@ impl Drop for Tracee {
             }
         }
         // resume the tracee
-        self.resume();
+        if !matches!(self.state, State::Exited) {
+            self.resume();
+        }
     }
 }
@@ -2,7 +2,7 @@
 mod tests {
     use std::io::{Read, Seek, SeekFrom};
 
-    use intercept::{config, SpawnOptions, Tracer};
+    use intercept::{config, Recording, SpawnOptions, Tracer};
 
     fn test_config() -> config::Config {
         config::Config {
@@ -15,19 +15,46 @@ mod tests {
                 random: false,
                 time: false,
                 pid: false,
+                random_bytes: None,
+                denied: false,
+                faults: false,
+                network: false,
+                uname: false,
             },
             redirect: config::RedirectConfig {
                 files: vec![],
-                random: false,
+                network: vec![],
+                follow_symlinks: false,
+                random: config::RandomConfig {
+                    enabled: false,
+                    ..Default::default()
+                },
                 time: None,
+                timestamps: None,
                 pid: None,
+                identity: None,
+                dns: None,
+                uname: None,
                 stdout: None,
                 stderr: None,
             },
+            deny: vec![],
+            faults: config::FaultConfig::default(),
+            network: config::NetworkMode::default(),
+            vdso: config::VdsoMode::default(),
         }
     }
 
     fn run_command(conf: &config::Config, command: &str, args: &[&str]) -> std::io::Result<String> {
+        run_or_replay(conf, None, command, args)
+    }
+
+    fn run_or_replay(
+        conf: &config::Config,
+        recording: Option<Recording>,
+        command: &str,
+        args: &[&str],
+    ) -> std::io::Result<String> {
         let mut tmp = tempfile::tempfile()?;
         let clone = tmp.try_clone()?;
 
@@ -36,7 +63,10 @@ mod tests {
             stderr: None,
         };
         let tracer = Tracer::spawn(command, args, opts)?;
-        tracer.run(conf)?;
+        match recording {
+            Some(recording) => tracer.replay(conf, recording)?,
+            None => tracer.run(conf)?,
+        }
         let mut output = String::new();
         tmp.seek(SeekFrom::Start(0))?;
         tmp.read_to_string(&mut output)?;
@@ -86,4 +116,1102 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!("hello", result.unwrap().trim());
     }
+
+    #[test]
+    fn replay() {
+        let recording = tempfile::NamedTempFile::new().unwrap();
+        let mut conf = test_config();
+        conf.record.path = recording.path().into();
+        conf.record.time = true;
+        conf.record.pid = true;
+        let recorded = run_command(&conf, "date", &["+%s%N"]).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        let conf = test_config();
+        let recording = Recording::load(recording.path()).unwrap();
+        let replayed = run_or_replay(&conf, Some(recording), "date", &["+%s%N"]).unwrap();
+        assert_eq!(recorded, replayed);
+    }
+
+    #[test]
+    fn replay_reordered_ids() {
+        let recording = tempfile::NamedTempFile::new().unwrap();
+        let mut conf = test_config();
+        conf.record.path = recording.path().into();
+        conf.record.pid = true;
+        let script = "import os; print(os.getpid(), os.getuid())";
+        let recorded = run_command(&conf, "python3", &["-c", script]).unwrap();
+
+        // Each value is replayed for the syscall that returned it.
+        let conf = test_config();
+        let recording = Recording::load(recording.path()).unwrap();
+        let script = "import os; print(os.getuid(), os.getpid())";
+        let replayed = run_or_replay(&conf, Some(recording), "python3", &["-c", script]).unwrap();
+        let recorded: Vec<&str> = recorded.split_whitespace().rev().collect();
+        assert_eq!(recorded.join(" "), replayed.trim());
+    }
+
+    #[test]
+    fn replay_random() {
+        let recording = tempfile::NamedTempFile::new().unwrap();
+        let mut conf = test_config();
+        conf.record.path = recording.path().into();
+        conf.record.random = true;
+        conf.record.random_bytes = Some(config::BytesEncoding::Hex);
+        let script = "import os; print(os.urandom(16).hex())";
+        let recorded = run_command(&conf, "python3", &["-c", script]).unwrap();
+
+        let conf = test_config();
+        let recording = Recording::load(recording.path()).unwrap();
+        let replayed = run_or_replay(&conf, Some(recording), "python3", &["-c", script]).unwrap();
+        assert_eq!(recorded, replayed);
+    }
+
+    #[test]
+    fn seeded_random() {
+        let script = "import os; print(os.urandom(8).hex())";
+        let mut conf = test_config();
+        conf.redirect.random = config::RandomConfig {
+            seed: 1,
+            algorithm: config::RandomAlgorithm::ChaCha20,
+            ..Default::default()
+        };
+        let first = run_command(&conf, "python3", &["-c", script]).unwrap();
+        let again = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_eq!(first, again);
+
+        // rand's generator stays the default, as before the algorithm setting.
+        conf.redirect.random = config::RandomConfig {
+            seed: 1,
+            ..Default::default()
+        };
+        let default = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_ne!(first, default);
+        conf.redirect.random.algorithm = config::RandomAlgorithm::Std;
+        let std = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_eq!(default, std);
+
+        conf.redirect.random.seed = 2;
+        let other = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_ne!(std, other);
+
+        conf.redirect.random.algorithm = config::RandomAlgorithm::Constant;
+        conf.redirect.random.fill = 0xab;
+        let constant = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_eq!("abababababababab", constant.trim());
+    }
+
+    #[test]
+    fn random_device() {
+        let script = "print(open('/dev/urandom', 'rb').read(8).hex())";
+        let mut conf = test_config();
+        conf.redirect.random = config::RandomConfig {
+            seed: 1,
+            algorithm: config::RandomAlgorithm::ChaCha20,
+            ..Default::default()
+        };
+        let first = run_command(&conf, "python3", &["-c", script]).unwrap();
+        let again = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_eq!(first, again);
+        assert_ne!("0000000000000000", first.trim());
+
+        conf.redirect.random.algorithm = config::RandomAlgorithm::Constant;
+        conf.redirect.random.fill = 0xab;
+        let constant = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_eq!("abababababababab", constant.trim());
+
+        // Reused fds are not mistaken for the device they were.
+        let script = "import os
+fd = os.open('/dev/urandom', os.O_RDONLY)
+print(os.read(fd, 4).hex())
+os.close(fd)
+print(os.open('/dev/zero', os.O_RDONLY) == fd, os.read(fd, 4).hex())
+os.dup2(os.open('/dev/urandom', os.O_RDONLY), fd)
+print(os.read(fd, 4).hex())";
+        let result = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_eq!("abababab\nTrue 00000000\nabababab", result.trim());
+
+        // Shortened reads get as many bytes as the shortened count.
+        conf.faults.rules.push(config::FaultRule {
+            syscall: Some(intercept::SysNum::Read),
+            path: Some(config::Pattern("/dev/urandom".to_string())),
+            errno: None,
+            short: Some(2),
+            every: None,
+            probability: None,
+        });
+        let result = run_command(&conf, "python3", &["-c", script]).unwrap();
+        assert_eq!("abab\nTrue 00000000\nabab", result.trim());
+    }
+
+    #[test]
+    fn intercepted_openat() {
+        let mut conf = test_config();
+        conf.redirect.files.push(config::Redirect {
+            from: "/etc/passwd".to_string(),
+            to: "/dev/null".to_string(),
+        });
+        let script = "import os
+fd = os.open('/etc', os.O_RDONLY)
+print(open('passwd', opener=lambda p, f: os.open(p, f, dir_fd=fd)).read())";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert!(result.unwrap().trim().is_empty());
+    }
+
+    #[test]
+    fn intercepted_relative_path() {
+        let mut conf = test_config();
+        conf.redirect.files.push(config::Redirect {
+            from: "/etc/passwd".to_string(),
+            to: "/dev/null".to_string(),
+        });
+        let result = run_command(&conf, "bash", &["-c", "cd /usr && cat ./../etc/passwd"]);
+        assert!(result.is_ok());
+        assert!(result.unwrap().trim().is_empty());
+    }
+
+    #[test]
+    fn intercepted_openat2() {
+        let mut conf = test_config();
+        conf.redirect.files.push(config::Redirect {
+            from: "/etc/passwd".to_string(),
+            to: "/dev/null".to_string(),
+        });
+        let script = "import ctypes, os
+libc = ctypes.CDLL(None)
+how = (ctypes.c_uint64 * 3)(os.O_RDONLY, 0, 0)
+fd = libc.syscall(437, -100, b'/etc/passwd', ctypes.byref(how), 24)
+print(os.read(fd, 100).decode())";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert!(result.unwrap().trim().is_empty());
+    }
+
+    #[test]
+    fn intercepted_creat() {
+        let dest = tempfile::NamedTempFile::new().unwrap();
+        let mut conf = test_config();
+        conf.redirect.files.push(config::Redirect {
+            from: "/nonexistent/creat".to_string(),
+            to: dest.path().to_string_lossy().to_string(),
+        });
+        let script = "import ctypes, os
+libc = ctypes.CDLL(None)
+fd = libc.syscall(85, b'/nonexistent/creat', 0o644)
+os.write(fd, b'hello')";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("hello", std::fs::read_to_string(dest.path()).unwrap());
+    }
+
+    #[test]
+    fn failed_open_by_handle() {
+        let recording = tempfile::NamedTempFile::new().unwrap();
+        let mut conf = test_config();
+        conf.record.path = recording.path().to_path_buf();
+        conf.record.files = true;
+        let script = "import ctypes
+libc = ctypes.CDLL(None)
+handle = (ctypes.c_uint32 * 4)(8, 1, 0, 0)
+print(libc.syscall(304, -1, ctypes.byref(handle), 0))";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("-1", result.unwrap().trim());
+        let records = std::fs::read_to_string(recording.path()).unwrap();
+        assert!(!records.contains(r#""path":"""#));
+    }
+
+    #[test]
+    fn intercepted_stat() {
+        let mut conf = test_config();
+        conf.redirect.files.push(config::Redirect {
+            from: "/etc/passwd".to_string(),
+            to: "/dev/null".to_string(),
+        });
+        conf.redirect.files.push(config::Redirect {
+            from: "/etc/hostname".to_string(),
+            to: "/nonexistent/hostname".to_string(),
+        });
+        let script = "import os
+print(os.stat('/etc/passwd').st_size, os.access('/etc/hostname', os.F_OK))";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("0 False", result.unwrap().trim());
+    }
+
+    #[test]
+    fn intercepted_unlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        std::fs::write(&from, "from").unwrap();
+        std::fs::write(&to, "to").unwrap();
+        let recording = dir.path().join("recording");
+
+        let mut conf = test_config();
+        conf.record.path = recording.clone();
+        conf.record.files = true;
+        conf.redirect.files.push(config::Redirect {
+            from: from.to_string_lossy().to_string(),
+            to: to.to_string_lossy().to_string(),
+        });
+        let result = run_command(&conf, "rm", &[&from.to_string_lossy()]);
+        assert!(result.is_ok());
+        assert!(from.exists());
+        assert!(!to.exists());
+
+        let records = std::fs::read_to_string(recording).unwrap();
+        assert!(records.contains(r#""type":"change","syscall":"unlinkat""#));
+
+        // Relative paths are recorded resolved against the working directory.
+        let script = "import os, sys
+os.chdir(sys.argv[1])
+os.mkdir('sub')
+os.rmdir('./sub/../sub')";
+        let result = run_command(
+            &conf,
+            "python3",
+            &["-c", script, &dir.path().to_string_lossy()],
+        );
+        assert!(result.is_ok());
+        let records = std::fs::read_to_string(&conf.record.path).unwrap();
+        let sub = dir.path().join("sub");
+        assert!(records.contains(&format!(
+            r#""syscall":"mkdir","paths":["{}"]"#,
+            sub.display()
+        )));
+        assert!(records.contains(&format!(
+            r#""syscall":"rmdir","paths":["{}"]"#,
+            sub.display()
+        )));
+    }
+
+    #[test]
+    fn denied() {
+        let mut conf = test_config();
+        conf.deny.push(config::DenyRule {
+            syscall: None,
+            path: Some(config::Pattern("/etc/pass*".to_string())),
+            errno: nix::errno::Errno::ENOENT,
+        });
+        conf.deny.push(config::DenyRule {
+            syscall: Some(intercept::SysNum::Socket),
+            path: None,
+            errno: nix::errno::Errno::EACCES,
+        });
+        let script = "import errno, os, socket
+try:
+    open('/etc/passwd')
+except OSError as e:
+    print(errno.errorcode[e.errno])
+try:
+    socket.socket()
+except OSError as e:
+    print(errno.errorcode[e.errno])
+print(os.path.exists('/etc/hostname'))";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("ENOENT\nEACCES\nTrue", result.unwrap().trim());
+    }
+
+    #[test]
+    fn faults() {
+        let output = tempfile::NamedTempFile::new().unwrap();
+        let output = output.path().to_string_lossy().to_string();
+        let mut conf = test_config();
+        conf.faults.rules.push(config::FaultRule {
+            syscall: Some(intercept::SysNum::Write),
+            path: Some(config::Pattern(output.clone())),
+            errno: Some(nix::errno::Errno::ENOSPC),
+            short: None,
+            every: Some(2),
+            probability: None,
+        });
+        let script = "import errno, os, sys
+fd = os.open(sys.argv[1], os.O_WRONLY)
+results = []
+for _ in range(4):
+    try:
+        results.append(str(os.write(fd, b'hello')))
+    except OSError as e:
+        results.append(errno.errorcode[e.errno])
+print(' '.join(results))";
+        let result = run_command(&conf, "python3", &["-c", script, &output]);
+        assert!(result.is_ok());
+        assert_eq!("5 ENOSPC 5 ENOSPC", result.unwrap().trim());
+
+        let mut conf = test_config();
+        conf.faults.rules.push(config::FaultRule {
+            syscall: Some(intercept::SysNum::Read),
+            path: Some(config::Pattern("/etc/passwd".to_string())),
+            errno: None,
+            short: Some(2),
+            every: None,
+            probability: None,
+        });
+        let script = "import os
+fd = os.open('/etc/passwd', os.O_RDONLY)
+print(len(os.read(fd, 100)))";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("2", result.unwrap().trim());
+
+        // Probabilistic faults are the same from one run to the next.
+        let mut conf = test_config();
+        conf.faults.seed = 42;
+        conf.faults.rules.push(config::FaultRule {
+            syscall: Some(intercept::SysNum::Write),
+            path: Some(config::Pattern(output.clone())),
+            errno: Some(nix::errno::Errno::EIO),
+            short: None,
+            every: None,
+            probability: Some(0.5),
+        });
+        let script = "import os, sys
+fd = os.open(sys.argv[1], os.O_WRONLY)
+results = []
+for _ in range(32):
+    try:
+        os.write(fd, b'x')
+        results.append('.')
+    except OSError:
+        results.append('E')
+print(''.join(results))";
+        let first = run_command(&conf, "python3", &["-c", script, &output]).unwrap();
+        let second = run_command(&conf, "python3", &["-c", script, &output]).unwrap();
+        assert_eq!(first, second);
+        assert!(first.contains('E') && first.contains('.'));
+        conf.faults.seed = 43;
+        let other = run_command(&conf, "python3", &["-c", script, &output]).unwrap();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn invalid_fault_rule() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let yaml = "log: {}
+record: {files: false, random: false, time: false, pid: false, path: /dev/null}
+redirect: {files: [], random: false, pid: null, stdout: null, stderr: null}
+faults:
+  rules:
+    - syscall: write
+      every: 2
+";
+        std::io::Write::write_all(&mut file, yaml.as_bytes()).unwrap();
+        let path = file.path().to_string_lossy().to_string();
+        let error = config::Config::load(&path).unwrap_err();
+        assert!(error.to_string().contains("errno or a short count"));
+
+        let yaml = yaml.replace("every: 2", "errno: EIO");
+        std::fs::write(&path, yaml).unwrap();
+        assert!(config::Config::load(&path).is_ok());
+    }
+
+    #[test]
+    fn invalid_deny_rule() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let yaml = "log: {}
+record: {files: false, random: false, time: false, pid: false, path: /dev/null}
+redirect: {files: [], random: false, pid: null, stdout: null, stderr: null}
+deny:
+  - errno: EPERM
+";
+        std::io::Write::write_all(&mut file, yaml.as_bytes()).unwrap();
+        let path = file.path().to_string_lossy().to_string();
+        let error = config::Config::load(&path).unwrap_err();
+        assert!(error.to_string().contains("a syscall or a path"));
+
+        for errno in ["0", "-1", "4096"] {
+            let yaml = yaml.replace("EPERM", &format!("{}\n    syscall: socket", errno));
+            std::fs::write(&path, yaml).unwrap();
+            let error = config::Config::load(&path).unwrap_err();
+            assert!(error.to_string().contains("invalid errno"));
+        }
+
+        let yaml = yaml.replace("EPERM", "4095\n    syscall: socket");
+        std::fs::write(&path, yaml).unwrap();
+        assert!(config::Config::load(&path).is_ok());
+    }
+
+    #[test]
+    fn virtual_clocks() {
+        let mut conf = test_config();
+        conf.redirect.time = Some(config::TimeConfig {
+            monotonic: Some(1000),
+            cpu: config::CpuTimePolicy::Step,
+            cpu_step: 1_000_000,
+            ..config::TimeConfig::frozen(1_700_000_000)
+        });
+        let script = "import time
+print(time.time(), time.monotonic())
+print(time.thread_time_ns(), time.thread_time_ns())";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!(
+            "1700000000.0 1000.0\n1000000 2000000",
+            result.unwrap().trim()
+        );
+    }
+
+    #[test]
+    fn legacy_time_calls() {
+        let mut conf = test_config();
+        conf.redirect.time = Some(config::TimeConfig::frozen(1_700_000_000));
+        let script = "import ctypes
+libc = ctypes.CDLL(None)
+tv = (ctypes.c_long * 2)()
+libc.gettimeofday(tv, None)
+t = ctypes.c_long()
+r = libc.time(ctypes.byref(t))
+res = (ctypes.c_long * 2)()
+libc.clock_getres(5, res)
+timex = (ctypes.c_char * 208)()
+libc.adjtimex(timex)
+secs = ctypes.c_long.from_buffer(timex, 72).value
+print(tv[0], tv[1], r, t.value, res[0], res[1], secs)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!(
+            "1700000000 0 1700000000 1700000000 0 1 1700000000",
+            result.unwrap().trim()
+        );
+    }
+
+    #[test]
+    fn virtual_sleep() {
+        let mut conf = test_config();
+        conf.redirect.time = Some(config::TimeConfig {
+            monotonic: Some(1000),
+            sleep: true,
+            ..config::TimeConfig::frozen(1_700_000_000)
+        });
+        let script = "import time, select
+a = time.time()
+time.sleep(10)
+select.select([], [], [], 5)
+select.poll().poll(2000)
+print(time.time() - a, time.monotonic())";
+        let start = std::time::Instant::now();
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("17.0 1017.0", result.unwrap().trim());
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn endless_virtual_sleep() {
+        let mut conf = test_config();
+        conf.redirect.time = Some(config::TimeConfig {
+            sleep: true,
+            ..config::TimeConfig::frozen(1_700_000_000)
+        });
+        // The clocks stop at the largest nanosecond timestamp.
+        let script = "import ctypes, time
+libc = ctypes.CDLL(None)
+forever = (ctypes.c_int64 * 2)(2 ** 63 - 1, 0)
+print(libc.nanosleep(forever, None), time.time_ns() == 2 ** 63 - 1)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("0 True", result.unwrap().trim());
+    }
+
+    #[test]
+    fn patched_vdso() {
+        let mut conf = test_config();
+        conf.vdso = config::VdsoMode::Patch;
+        conf.redirect.time = Some(config::TimeConfig::frozen(1_700_000_000));
+        let script = "import ctypes, time
+libc = ctypes.CDLL(None)
+libc.getauxval.restype = ctypes.c_ulong
+print(time.time(), libc.getauxval(33) != 0)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("1700000000.0 True", result.unwrap().trim());
+    }
+
+    #[test]
+    fn fake_timestamps() {
+        let mut conf = test_config();
+        conf.redirect.timestamps = Some(config::TimestampConfig {
+            epoch: Some(1_234_567_890),
+            paths: vec![config::Pattern("/etc/*".to_string())],
+        });
+        let script = "import os
+with open('/etc/passwd') as f:
+    print(os.stat('/etc/passwd').st_mtime_ns, os.fstat(f.fileno()).st_ctime)
+print(os.stat('/usr').st_mtime != 1234567890)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!(
+            "1234567890000000000 1234567890.0\nTrue",
+            result.unwrap().trim()
+        );
+
+        let result = run_command(&conf, "stat", &["-c", "%Y %X", "/etc/hostname"]);
+        assert!(result.is_ok());
+        assert_eq!("1234567890 1234567890", result.unwrap().trim());
+
+        // Without an epoch, the timestamps need a virtual clock.
+        conf.redirect.timestamps = Some(config::TimestampConfig {
+            epoch: None,
+            paths: vec![],
+        });
+        assert!(run_command(&conf, "true", &[]).is_err());
+    }
+
+    #[test]
+    fn network_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("recording");
+        let mut conf = test_config();
+        conf.record.path = recording.clone();
+        conf.record.network = true;
+        let script = "import socket
+server = socket.socket()
+server.bind(('127.0.0.1', 0))
+server.listen()
+client = socket.create_connection(server.getsockname())
+server.accept()
+udp = socket.socket(socket.AF_INET6, socket.SOCK_DGRAM)
+udp.sendto(b'x', ('::1', 9))
+try:
+    socket.socket(socket.AF_UNIX).connect('/nonexistent/socket')
+except OSError:
+    pass";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+
+        let records = std::fs::read_to_string(recording).unwrap();
+        assert!(records.contains(r#""syscall":"socket","family":"inet","kind":"stream""#));
+        assert!(records.contains(r#""syscall":"bind","address":"127.0.0.1:0","success":true"#));
+        assert!(records.contains(r#""syscall":"connect","address":"127.0.0.1:"#));
+        assert!(records.contains(r#""syscall":"accept4","address":"127.0.0.1:"#));
+        assert!(records.contains(r#""syscall":"sendto","address":"[::1]:9","success":true"#));
+        assert!(records.contains(
+            r#""syscall":"connect","address":"/nonexistent/socket","success":false,"errno":"ENOENT""#
+        ));
+    }
+
+    #[test]
+    fn network_redirects() {
+        use std::net::TcpListener;
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("socket");
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let unix = UnixListener::bind(&socket).unwrap();
+        let mut conf = test_config();
+        conf.redirect.network.push(config::Redirect {
+            from: "10.1.2.3:443".to_string(),
+            to: tcp.local_addr().unwrap().to_string(),
+        });
+        conf.redirect.network.push(config::Redirect {
+            from: "10.9.9.0/24".to_string(),
+            to: socket.to_string_lossy().to_string(),
+        });
+        let tcp = std::thread::spawn(move || {
+            let mut data = String::new();
+            tcp.accept().unwrap().0.read_to_string(&mut data).unwrap();
+            data
+        });
+        let unix = std::thread::spawn(move || {
+            let mut data = String::new();
+            unix.accept().unwrap().0.read_to_string(&mut data).unwrap();
+            data
+        });
+
+        let script = "import socket
+s = socket.create_connection(('10.1.2.3', 443), timeout=5)
+s.sendall(b'tcp')
+s.close()
+u = socket.socket()
+u.connect(('10.9.9.7', 80))
+u.sendall(b'unix')
+u.close()";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("tcp", tcp.join().unwrap());
+        assert_eq!("unix", unix.join().unwrap());
+    }
+
+    #[test]
+    fn mapped_network_redirects() {
+        use std::net::TcpListener;
+
+        // IPv6 sockets reach the IPv4 destination through a mapped address.
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = tcp.local_addr().unwrap().port();
+        let mut conf = test_config();
+        conf.redirect.network.push(config::Redirect {
+            from: "10.1.2.0/24".to_string(),
+            to: tcp.local_addr().unwrap().to_string(),
+        });
+        let tcp = std::thread::spawn(move || {
+            let mut data = String::new();
+            tcp.accept().unwrap().0.read_to_string(&mut data).unwrap();
+            data
+        });
+        let script = "import socket
+s = socket.socket(socket.AF_INET6)
+s.settimeout(5)
+s.connect(('::ffff:10.1.2.3', 443))
+s.sendall(b'mapped')
+print(s.getpeername()[1])
+s.close()";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!(port.to_string(), result.unwrap().trim());
+        assert_eq!("mapped", tcp.join().unwrap());
+
+        // Host names that can't be resolved are configuration errors.
+        conf.redirect.network[0].from = "nonexistent.invalid:443".to_string();
+        let result = run_command(&conf, "true", &[]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fake_uname() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("recording");
+        let mut conf = test_config();
+        conf.record.path = recording.clone();
+        conf.record.uname = true;
+        conf.redirect.uname = Some(config::UnameConfig {
+            nodename: Some("builder".to_string()),
+            release: Some("6.1.0-reproducible".to_string()),
+            ..Default::default()
+        });
+        let result = run_command(&conf, "uname", &["-nrs"]);
+        assert!(result.is_ok());
+        assert_eq!("Linux builder 6.1.0-reproducible", result.unwrap().trim());
+
+        // The real names are recorded.
+        let real = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap();
+        let records = std::fs::read_to_string(recording).unwrap();
+        assert!(records.contains(&format!(r#""release":"{}""#, real.trim())));
+
+        let result = run_command(
+            &conf,
+            "cat",
+            &["/proc/sys/kernel/hostname", "/etc/hostname"],
+        );
+        assert!(result.is_ok());
+        assert_eq!("builder\nbuilder", result.unwrap().trim());
+
+        // A hostname file set in the configuration is kept.
+        conf.redirect.files.push(config::Redirect {
+            from: "/etc/hostname".to_string(),
+            to: "/dev/null".to_string(),
+        });
+        let result = run_command(&conf, "cat", &["/etc/hostname"]);
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn deterministic_dns() {
+        let mut conf = test_config();
+        conf.network = config::NetworkMode::Offline;
+        conf.redirect.dns = Some(config::DnsConfig {
+            hosts: vec![
+                config::HostEntry {
+                    name: "api.example.com".to_string(),
+                    address: "127.0.0.42".parse().unwrap(),
+                },
+                config::HostEntry {
+                    name: "api.example.com".to_string(),
+                    address: "fd00::42".parse().unwrap(),
+                },
+            ],
+            resolver: true,
+        });
+        let script = r#"import socket, struct
+print(socket.gethostbyname('api.example.com'))
+try:
+    socket.gethostbyname('unknown.example.com')
+except socket.gaierror:
+    print('unknown')
+def query(name, kind, tcp=False):
+    labels = b''.join(bytes([len(l)]) + l.encode() for l in name.split('.'))
+    message = struct.pack('>6H', 7, 0x100, 1, 0, 0, 0) + labels + b'\0' + struct.pack('>2H', kind, 1)
+    if tcp:
+        s = socket.create_connection(('198.51.100.1', 53), timeout=5)
+        s.sendall(struct.pack('>H', len(message)) + message)
+        length, = struct.unpack('>H', s.recv(2))
+        data = s.recv(length)
+    else:
+        s = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        s.settimeout(5)
+        s.sendto(message, ('198.51.100.1', 53))
+        data = s.recv(512)
+    flags, _, answers = struct.unpack('>3H', data[2:8])
+    return flags & 0xf, answers, data[-16:]
+print(query('API.example.com', 1)[:2], socket.inet_ntoa(query('api.example.com', 1)[2][-4:]))
+print(query('api.example.com', 28)[2] == socket.inet_pton(socket.AF_INET6, 'fd00::42'))
+print(query('missing.example.com', 1)[:2])
+print(socket.inet_ntoa(query('api.example.com', 1, tcp=True)[2][-4:]))"#;
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!(
+            "127.0.0.42\nunknown\n(0, 1) 127.0.0.42\nTrue\n(3, 0)\n127.0.0.42",
+            result.unwrap().trim()
+        );
+
+        // The host's files are kept, except for the configured names and
+        // where host names come from.
+        conf.redirect.dns = Some(config::DnsConfig {
+            hosts: vec![config::HostEntry {
+                name: "localhost".to_string(),
+                address: "127.0.0.43".parse().unwrap(),
+            }],
+            resolver: false,
+        });
+        let script = "import pwd, socket
+print(socket.gethostbyname('localhost'))
+lines = open('/etc/nsswitch.conf').read().splitlines()
+print([line for line in lines if line.split(':')[0] in ('hosts', 'passwd')])
+print(pwd.getpwuid(0).pw_name)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        let nsswitch = std::fs::read_to_string("/etc/nsswitch.conf").unwrap_or_default();
+        let lines: Vec<String> = nsswitch
+            .lines()
+            .map(|line| match line.starts_with("hosts:") {
+                true => "hosts: files".to_string(),
+                false => line.to_string(),
+            })
+            .filter(|line| line.starts_with("hosts:") || line.starts_with("passwd:"))
+            .map(|line| format!("'{}'", line))
+            .collect();
+        assert_eq!(
+            format!("127.0.0.43\n[{}]\nroot", lines.join(", ")),
+            result.unwrap().trim()
+        );
+
+        // Redirections set in the configuration win over the generated ones.
+        let hosts = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(hosts.path(), "127.0.0.44 localhost\n").unwrap();
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        conf.redirect.dns.as_mut().unwrap().resolver = true;
+        conf.redirect.files.push(config::Redirect {
+            from: "/etc/hosts".to_string(),
+            to: hosts.path().to_string_lossy().to_string(),
+        });
+        conf.redirect.network.push(config::Redirect {
+            from: "198.51.100.1:53".to_string(),
+            to: server.local_addr().unwrap().to_string(),
+        });
+        let script = "import socket
+print(socket.gethostbyname('localhost'))
+socket.socket(socket.AF_INET, socket.SOCK_DGRAM).sendto(b'query', ('198.51.100.1', 53))";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("127.0.0.44", result.unwrap().trim());
+        let mut data = [0; 16];
+        let len = server.recv(&mut data).unwrap();
+        assert_eq!(b"query", &data[..len]);
+    }
+
+    #[test]
+    fn offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("recording");
+        let mut conf = test_config();
+        conf.network = config::NetworkMode::Offline;
+        conf.record.path = recording.clone();
+        conf.record.network = true;
+        let script = "import socket
+try:
+    socket.create_connection(('192.0.2.1', 80))
+except OSError as e:
+    print(e.errno == 101)
+try:
+    socket.socket(socket.AF_INET6, socket.SOCK_DGRAM).sendto(b'x', ('2001:db8::1', 53))
+except OSError as e:
+    print(e.errno == 101)
+try:
+    socket.socket(socket.AF_INET, socket.SOCK_DGRAM).sendmsg([b'x'], [], 0, ('192.0.2.1', 443))
+except OSError as e:
+    print(e.errno == 101)
+socket.socket(socket.AF_INET, socket.SOCK_DGRAM).sendto(b'x', ('127.0.0.1', 9))
+print('local')";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("True\nTrue\nTrue\nlocal", result.unwrap().trim());
+
+        let records = std::fs::read_to_string(&recording).unwrap();
+        assert!(records.contains(
+            r#""syscall":"connect","address":"192.0.2.1:80","success":false,"errno":"ENETUNREACH","blocked":true"#
+        ));
+        assert!(records.contains(r#""syscall":"sendmsg","address":"192.0.2.1:443""#));
+        assert!(records.contains(r#""syscall":"sendto","address":"127.0.0.1:9","success":true"#));
+
+        // Messages are redirected like other sends, and blocked attempts are
+        // recorded whatever the configuration.
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        conf.record.network = false;
+        conf.redirect.network.push(config::Redirect {
+            from: "10.3.3.3:5353".to_string(),
+            to: server.local_addr().unwrap().to_string(),
+        });
+        let script = "import socket
+msg = ([b'redirected'], [], 0, ('10.3.3.3', 5353))
+print(socket.socket(socket.AF_INET, socket.SOCK_DGRAM).sendmsg(*msg))
+try:
+    socket.socket(socket.AF_INET, socket.SOCK_DGRAM).sendmsg([b'x'], [], 0, ('192.0.2.1', 443))
+except OSError as e:
+    print(e.errno == 101)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("10\nTrue", result.unwrap().trim());
+        let mut data = [0; 16];
+        let len = server.recv(&mut data).unwrap();
+        assert_eq!(b"redirected", &data[..len]);
+        let records = std::fs::read_to_string(&recording).unwrap();
+        assert_eq!(1, records.lines().count());
+        assert!(records.contains(r#""blocked":true"#));
+    }
+
+    #[test]
+    fn blocking_sends() {
+        // Sends wait for another thread to drain the socket, which the tracer
+        // must keep serving meanwhile.
+        let recording = tempfile::NamedTempFile::new().unwrap();
+        let mut conf = test_config();
+        let script = "import os, socket, sys, threading
+path = os.path.join(sys.argv[1], 'socket')
+server = socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM)
+server.bind(path)
+client = socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM)
+def drain():
+    for _ in range(2000):
+        server.recv(16)
+thread = threading.Thread(target=drain)
+thread.start()
+for _ in range(2000):
+    client.sendto(b'x' * 16, path)
+thread.join()
+print('done')";
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_string_lossy();
+        let result = run_command(&conf, "python3", &["-c", script, &dir]);
+        assert!(result.is_ok());
+        assert_eq!("done", result.unwrap().trim());
+
+        conf.record.path = recording.path().into();
+        conf.record.network = true;
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_string_lossy();
+        let result = run_command(&conf, "python3", &["-c", script, &dir]);
+        assert!(result.is_ok());
+        assert_eq!("done", result.unwrap().trim());
+        let records = std::fs::read_to_string(recording.path()).unwrap();
+        assert_eq!(2000, records.matches(r#""syscall":"sendto""#).count());
+    }
+
+    #[test]
+    fn virtual_pids() {
+        let mut conf = test_config();
+        conf.redirect.pid = Some(1000);
+        let script = "import os, threading
+pid = os.fork()
+if pid == 0:
+    print(os.getpid(), os.getppid(), flush=True)
+    os.kill(os.getpid(), 9)
+_, status = os.waitpid(pid, 0)
+print(pid, os.getpid(), threading.get_native_id(), os.waitstatus_to_exitcode(status))";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        let output = result.unwrap();
+        let lines: Vec<Vec<i32>> = output
+            .lines()
+            .map(|line| line.split(' ').map(|n| n.parse().unwrap()).collect())
+            .collect();
+        // The child sees its parent and itself as the parent does.
+        let (child, parent) = (&lines[0], &lines[1]);
+        assert_eq!(child[0], parent[0]);
+        assert_eq!(child[1], 1000);
+        assert!(child[0] > 1000 && child[0] < 1100);
+        assert_eq!(&parent[1..], &[1000, 1000, -9]);
+    }
+
+    #[test]
+    fn reused_pids() {
+        // Force the host to give the PID of a reaped child to a new one, which
+        // must get the next virtual PID nonetheless.
+        let mut conf = test_config();
+        conf.redirect.pid = Some(1000);
+        let script = "import os
+def child():
+    r, w = os.pipe()
+    pid = os.fork()
+    if pid == 0:
+        os.write(w, f'{os.getpid()} {os.readlink(\"/proc/self\")}'.encode())
+        os._exit(0)
+    os.waitpid(pid, 0)
+    virtual, real = os.read(r, 64).decode().split()
+    os.close(r)
+    os.close(w)
+    return pid, int(virtual), real
+first, virtual, real = child()
+for _ in range(20):
+    with open('/proc/sys/kernel/ns_last_pid', 'w') as f:
+        f.write(str(int(real) - 1))
+    pid, other, reused = child()
+    if reused == real:
+        print(first, virtual, pid, other)
+        break";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        let pids: Vec<u32> = result
+            .unwrap()
+            .split_whitespace()
+            .map(|pid| pid.parse().unwrap())
+            .collect();
+        assert_eq!(4, pids.len());
+        assert_eq!(pids[0], pids[1]);
+        assert_eq!(pids[2], pids[3]);
+        assert!(pids[2] > pids[0]);
+    }
+
+    #[test]
+    fn virtual_proc() {
+        let mut conf = test_config();
+        conf.redirect.pid = Some(1000);
+        let script = "import os
+status = dict(line.split(':\\t') for line in open('/proc/1000/status').read().splitlines())
+print(status['Pid'], status['PPid'], status['TracerPid'])
+print(open('/proc/self/stat').read().split()[:2], os.path.exists('/proc/1000/cmdline'))";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!(
+            "1000 0 0\n['1000', '(python3)'] True",
+            result.unwrap().trim()
+        );
+    }
+
+    #[test]
+    fn fake_identity() {
+        let mut conf = test_config();
+        conf.redirect.identity = Some(config::IdentityConfig {
+            uid: 4242,
+            gid: 4343,
+            username: Some("tester".to_string()),
+            groups: vec![4343, 27],
+        });
+        let script = "import os, pwd
+print(os.getuid(), os.geteuid(), os.getgid(), os.getegid())
+print(os.getresuid(), os.getresgid(), os.getgroups())
+print(pwd.getpwuid(os.getuid()).pw_name)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!(
+            "4242 4242 4343 4343\n(4242, 4242, 4242) (4343, 4343, 4343) [4343, 27]\ntester",
+            result.unwrap().trim()
+        );
+
+        // Fewer groups than the host user may have, even a single one.
+        conf.redirect.identity = Some(config::IdentityConfig {
+            uid: 4242,
+            gid: 4343,
+            username: None,
+            groups: vec![],
+        });
+        let script = "import os
+print(os.getgroups())";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("[4343]", result.unwrap().trim());
+
+        // Bad pointers fail the syscalls with EFAULT.
+        let script = "import ctypes
+libc = ctypes.CDLL(None, use_errno=True)
+ids = (ctypes.c_uint * 2)()
+for syscall, args in [(118, (ids, ids, 8)), (120, (8, ids, ids)), (115, (1, 8))]:
+    print(libc.syscall(syscall, *args), ctypes.get_errno())";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("-1 14\n-1 14\n-1 14", result.unwrap().trim());
+
+        // A passwd file set in the configuration is kept.
+        conf.redirect.identity = Some(config::IdentityConfig {
+            uid: 4242,
+            gid: 4343,
+            username: Some("tester".to_string()),
+            groups: vec![],
+        });
+        conf.redirect.files.push(config::Redirect {
+            from: "/etc/passwd".to_string(),
+            to: "/dev/null".to_string(),
+        });
+        let result = run_command(&conf, "cat", &["/etc/passwd"]);
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn time_modes() {
+        let mut conf = test_config();
+        conf.redirect.time = Some(config::TimeConfig {
+            mode: config::TimeMode::Step,
+            step: 500_000_000,
+            ..config::TimeConfig::frozen(1_700_000_000)
+        });
+        let script = "import time
+a = time.time()
+b = time.time()
+print(b - a, a >= 1700000000)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("0.5 True", result.unwrap().trim());
+
+        conf.redirect.time = Some(config::TimeConfig {
+            mode: config::TimeMode::Offset,
+            ..config::TimeConfig::frozen(1_700_000_000)
+        });
+        let script = "import time
+print(1700000000 <= time.time() < 1700000060)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("True", result.unwrap().trim());
+
+        // Huge scales stop the clocks at their latest time.
+        conf.redirect.time = Some(config::TimeConfig {
+            mode: config::TimeMode::Scaled,
+            scale: 1e300,
+            ..config::TimeConfig::frozen(1_700_000_000)
+        });
+        let script = "import time
+time.sleep(0.01)
+print(time.time_ns() == 2**63 - 1)";
+        let result = run_command(&conf, "python3", &["-c", script]);
+        assert!(result.is_ok());
+        assert_eq!("True", result.unwrap().trim());
+    }
+
+    #[test]
+    fn invalid_time_scale() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let yaml = "log: {}
+record: {files: false, random: false, time: false, pid: false, path: /dev/null}
+redirect:
+  files: []
+  random: false
+  pid: null
+  stdout: null
+  stderr: null
+  time: {realtime: 0, mode: scaled, scale: .inf}
+";
+        std::io::Write::write_all(&mut file, yaml.as_bytes()).unwrap();
+        let path = file.path().to_string_lossy().to_string();
+        let error = config::Config::load(&path).unwrap_err();
+        assert!(error.to_string().contains("time scale"));
+
+        std::fs::write(&path, yaml.replace(".inf", "-1")).unwrap();
+        assert!(config::Config::load(&path).is_err());
+        std::fs::write(&path, yaml.replace(".inf", "2.5")).unwrap();
+        assert!(config::Config::load(&path).is_ok());
+    }
 }